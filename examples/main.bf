@@ -1,8 +1,7 @@
-$std.bf
-$stdstr.bf
+$@std
 
 >+++++++++++++
-U
-M
-<0>
-P
+@dup
+@mul
+<@zero>
+@print_num
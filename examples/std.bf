@@ -1,3 +1,8 @@
+; ZERO OUT 
+; >n
+; >0
+#0 [-]
+
 ; ADD
 ; >x   y
 ; >x+y 0
@@ -6,7 +11,7 @@
 ; SUBSTRACT
 ; >0 x   y 0 0
 ; >0 x-y 0 0 0
-#S 0>>[<[->]<]>>[[<+>-]>>]<<<
+#sub 0>>[<[->]<]>>[[<+>-]>>]<<<
 
 ; MULTIPLY
 ; >x y 0    0
@@ -23,17 +28,12 @@
 ; >n n 0
 #U [>+>+<<-]>>[<<+>>-]<<
 
-; ZERO OUT 
-; >n
-; >0
-#0 [-]
-
 ; SHIFT
 ; >n *
 ; >0 n
 #S [>+<-]0
 
 ; SWAP
-: >x y 0
-: >y x 0
+; >x y 0
+; >y x 0
 #W [>>+<<-]>[<+>-]>[<+>-]<<
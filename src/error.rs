@@ -0,0 +1,171 @@
+use crate::span::Span;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors that can occur while tokenizing, parsing, transpiling or running a Braincrap program.
+#[derive(Debug)]
+pub enum BraincrapError {
+    /// A macro was defined with a reserved character as its name.
+    IllegalMacroName { name: char, span: Span },
+    /// An imported file could not be read. `span` is `None` for the file passed on the command line.
+    ImportNotFound { path: PathBuf, span: Option<Span> },
+    /// A file ended up importing itself, directly or through other files.
+    /// `chain` starts and ends with the same file, `span` is the import that closes the cycle.
+    ImportCycle { chain: Vec<PathBuf>, span: Span },
+    /// A `[` without a matching `]`, or the other way around.
+    UnbalancedBrackets { bracket: char, span: Span },
+    /// A macro was run but never defined.
+    UndefinedMacro { name: String, span: Span },
+    /// A macro ended up calling itself. `chain` starts and ends with the same macro, `span` is
+    /// the call that closes the cycle.
+    RecursiveMacro { chain: Vec<String>, span: Span },
+    /// A macro was called with too few or too many arguments.
+    /// `min` and `max` differ when some parameters have default values.
+    WrongArgumentCount {
+        name: String,
+        min: usize,
+        max: usize,
+        found: usize,
+        span: Span,
+    },
+    /// A macro parameter or argument is malformed, such as an unknown keyword argument or a
+    /// repeat count that is not a number.
+    InvalidMacroArgument {
+        name: String,
+        message: String,
+        span: Span,
+    },
+    /// The pointer moved outside of the tape.
+    PointerOutOfBounds { pointer: isize, span: Span },
+    /// A cell overflowed with `Overflow::Error`.
+    CellOverflow { pointer: usize, span: Span },
+    /// An instruction has no equivalent in the target language, such as a `MulAdd` that keeps
+    /// its source cell when transpiling to Brainfuck.
+    UnsupportedInstruction { message: String, span: Span },
+    /// A `TapeConfig` has an unsupported cell width or an empty tape.
+    InvalidTapeConfig { message: String },
+    /// Reading input or writing output failed.
+    IoError(io::Error),
+}
+
+impl BraincrapError {
+    /// Returns where in the source the error happened, if known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            BraincrapError::IllegalMacroName { span, .. }
+            | BraincrapError::UnbalancedBrackets { span, .. }
+            | BraincrapError::UndefinedMacro { span, .. }
+            | BraincrapError::RecursiveMacro { span, .. }
+            | BraincrapError::WrongArgumentCount { span, .. }
+            | BraincrapError::InvalidMacroArgument { span, .. }
+            | BraincrapError::PointerOutOfBounds { span, .. }
+            | BraincrapError::ImportCycle { span, .. }
+            | BraincrapError::CellOverflow { span, .. }
+            | BraincrapError::UnsupportedInstruction { span, .. } => Some(*span),
+            BraincrapError::ImportNotFound { span, .. } => *span,
+            BraincrapError::InvalidTapeConfig { .. } | BraincrapError::IoError(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for BraincrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BraincrapError::IllegalMacroName { name, .. } => {
+                write!(f, "Illegal macro name: {name:?}")
+            }
+            BraincrapError::ImportNotFound { path, .. } => {
+                write!(f, "Failed to read file: {}", path.display())
+            }
+            BraincrapError::ImportCycle { chain, .. } => {
+                let chain: Vec<String> = chain.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "Import cycle: {}", chain.join(" -> "))
+            }
+            BraincrapError::UnbalancedBrackets { bracket, .. } => {
+                write!(f, "Unmatched '{bracket}'")
+            }
+            BraincrapError::UndefinedMacro { name, .. } => write!(f, "Undefined macro: {name}"),
+            BraincrapError::RecursiveMacro { chain, .. } => {
+                write!(f, "Recursive macro: {}", chain.join(" -> "))
+            }
+            BraincrapError::WrongArgumentCount {
+                name,
+                min,
+                max,
+                found,
+                ..
+            } => {
+                let expected = if min == max {
+                    min.to_string()
+                } else {
+                    format!("{min} to {max}")
+                };
+                let plural = if *max == 1 { "" } else { "s" };
+                let were = if *found == 1 { "was" } else { "were" };
+                write!(
+                    f,
+                    "Macro {name} takes {expected} argument{plural} but {found} {were} given"
+                )
+            }
+            BraincrapError::InvalidMacroArgument { name, message, .. } => {
+                write!(f, "Invalid argument for macro {name}: {message}")
+            }
+            BraincrapError::PointerOutOfBounds { pointer, .. } => {
+                write!(f, "Pointer moved outside of the tape: {pointer}")
+            }
+            BraincrapError::CellOverflow { pointer, .. } => write!(f, "Cell {pointer} overflowed"),
+            BraincrapError::UnsupportedInstruction { message, .. } => {
+                write!(f, "Unsupported instruction: {message}")
+            }
+            BraincrapError::InvalidTapeConfig { message } => write!(f, "{message}"),
+            BraincrapError::IoError(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BraincrapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BraincrapError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BraincrapError {
+    fn from(e: io::Error) -> Self {
+        BraincrapError::IoError(e)
+    }
+}
+
+/// Problems that do not stop a program from being transpiled or run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BraincrapWarning {
+    /// A macro definition hides a macro that was already visible under the same name.
+    /// `span` is the new definition, `previous` the one it shadows.
+    ShadowedMacro {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+}
+
+impl BraincrapWarning {
+    /// Returns where in the source the warning happened.
+    pub fn span(&self) -> Span {
+        match self {
+            BraincrapWarning::ShadowedMacro { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for BraincrapWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BraincrapWarning::ShadowedMacro { name, .. } => {
+                write!(f, "Macro {name} shadows an existing definition")
+            }
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use crate::error::{BraincrapError, BraincrapWarning};
+use crate::parser::{
+    bind_arguments, match_loops, BraincrapCommand, ImportState, MacroParameter, Parser,
+};
+use crate::span::{SourceMap, Span, Spanned};
+use crate::tokenizer::{is_name_char, BraincrapToken, Lexer};
+use std::collections::HashMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// A macro as written in its definition.
+#[derive(Clone)]
+struct Macro {
+    name: String,
+    /// The file the macro is defined in, which its body is resolved against.
+    module: PathBuf,
+    parameters: Vec<MacroParameter>,
+    /// The body, with `%parameter` references left in place.
+    tokens: Vec<Spanned<BraincrapToken>>,
+    /// The parsed body, for macros without parameters.
+    code: Vec<Spanned<BraincrapCommand>>,
+    /// Where the macro is defined.
+    span: Span,
+}
+
+/// The macros of a single file.
+#[derive(Default)]
+struct Module {
+    /// Macros defined so far, mapping their names to their definitions.
+    macros: HashMap<String, Macro>,
+    /// The first definition of every macro in the file, used by calls made before it.
+    forward: HashMap<String, Macro>,
+    /// Files imported without an alias, whose public macros are visible in this file.
+    imports: Vec<PathBuf>,
+    /// Files imported with an alias, whose public macros are visible as `alias::name`.
+    aliases: HashMap<String, PathBuf>,
+}
+
+/// Returns whether a macro is private to the file it is defined in.
+fn is_private(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+/// Flattens macro definitions, macro calls and imports into primitive commands.
+///
+/// Every file has its own macros. Files imported without an alias share their public macros with
+/// the importer, files imported with `as` only through their alias, and macros whose name starts
+/// with `_` are private to their file. Macro bodies are expanded at every call, so they see the
+/// macros defined at that point, looked up in the file that defines the macro first and in the
+/// caller next, where only public macros are visible. A macro can be called before it is
+/// defined, in which case its first definition is used.
+///
+/// Expanded commands keep the span they were written at, so code coming from a macro body
+/// points into the macro definition rather than at the call.
+pub struct Expander {
+    /// The macros of every file, by canonical path. The main program is the empty path.
+    modules: HashMap<PathBuf, Module>,
+    /// The macros currently being expanded, outermost first, as (file, definition name, name as
+    /// called).
+    stack: Vec<(PathBuf, String, String)>,
+    /// Problems found so far that did not stop expansion.
+    warnings: Vec<BraincrapWarning>,
+    /// The files loaded by the parser, used when macro bodies are parsed again.
+    imports: ImportState,
+}
+
+impl Default for Expander {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Expander {
+    /// Creates a new `Expander` instance with no macros defined.
+    pub fn new() -> Self {
+        Self::with_imports(ImportState::default())
+    }
+
+    /// Creates a new `Expander` instance that parses macro bodies with the include directories,
+    /// loaded files and import directories of the parser the program came from.
+    ///
+    /// # Arguments
+    /// * `imports` - The import state returned by `Parser::into_imports`.
+    pub fn with_imports(imports: ImportState) -> Self {
+        Self {
+            modules: HashMap::new(),
+            stack: Vec::new(),
+            warnings: Vec::new(),
+            imports,
+        }
+    }
+
+    /// Returns every file loaded so far, including those imported by macro bodies.
+    pub fn sources(&self) -> &SourceMap {
+        self.imports.sources()
+    }
+
+    /// Consumes the expander, returning every file loaded so far.
+    pub fn into_sources(self) -> SourceMap {
+        self.imports.into_sources()
+    }
+
+    /// Returns the warnings found while expanding, such as macros shadowing each other.
+    pub fn warnings(&self) -> &[BraincrapWarning] {
+        &self.warnings
+    }
+
+    /// Expands every macro call and import in `commands`.
+    ///
+    /// Macros stay defined between calls, so a program can be expanded in several pieces.
+    ///
+    /// # Errors
+    /// Returns `BraincrapError::UndefinedMacro` if a macro is run but never defined,
+    /// `BraincrapError::RecursiveMacro` if a macro ends up calling itself, or an argument error if
+    /// a call does not match the parameters of its macro.
+    pub fn expand(
+        &mut self,
+        commands: Vec<Spanned<BraincrapCommand>>,
+    ) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+        let root = PathBuf::new();
+        self.collect(&commands, &root);
+        self.expand_commands(commands, &[root])
+    }
+
+    /// Records the first definition of every macro in `commands`, which belong to `module`, and
+    /// the files they import.
+    fn collect(&mut self, commands: &[Spanned<BraincrapCommand>], module: &Path) {
+        for command in commands {
+            match &command.node {
+                BraincrapCommand::DefineMacro {
+                    name,
+                    parameters,
+                    tokens,
+                    code,
+                } => {
+                    let forward = &mut self
+                        .modules
+                        .entry(module.to_path_buf())
+                        .or_default()
+                        .forward;
+                    forward.entry(name.clone()).or_insert_with(|| Macro {
+                        name: name.clone(),
+                        module: module.to_path_buf(),
+                        parameters: parameters.clone(),
+                        tokens: tokens.clone(),
+                        code: code.clone(),
+                        span: command.span,
+                    });
+                }
+                BraincrapCommand::Import {
+                    path, alias, code, ..
+                } => {
+                    self.link(module, path, alias.as_deref());
+                    self.collect(code, path);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Makes the macros of `import` visible in `module`, directly or through `alias`.
+    fn link(&mut self, module: &Path, import: &Path, alias: Option<&str>) {
+        self.modules.entry(import.to_path_buf()).or_default();
+        let module = self.modules.entry(module.to_path_buf()).or_default();
+        match alias {
+            Some(alias) => {
+                module
+                    .aliases
+                    .insert(alias.to_string(), import.to_path_buf());
+            }
+            None if !module.imports.iter().any(|i| i == import) => {
+                module.imports.push(import.to_path_buf());
+            }
+            None => {}
+        }
+    }
+
+    /// Finds the macro `name` refers to in `module`.
+    ///
+    /// # Arguments
+    /// * `module` - The file the name is looked up in.
+    /// * `name` - The name, optionally qualified with aliases such as `s::add`.
+    /// * `private` - Whether private macros of `module` can be found.
+    /// * `forward` - Whether definitions that come after the current point can be found.
+    fn lookup(&self, module: &Path, name: &str, private: bool, forward: bool) -> Option<&Macro> {
+        let scope = self.modules.get(module)?;
+        if let Some((alias, name)) = name.split_once("::") {
+            return self.lookup(scope.aliases.get(alias)?, name, false, forward);
+        }
+
+        let visible = private || !is_private(name);
+        scope
+            .macros
+            .get(name)
+            .filter(|_| visible)
+            .or_else(|| {
+                scope
+                    .imports
+                    .iter()
+                    .rev()
+                    .find_map(|import| self.lookup(import, name, false, forward))
+            })
+            .or_else(|| scope.forward.get(name).filter(|_| visible && forward))
+    }
+
+    /// Expands `commands`, defining macros as their definitions are reached.
+    ///
+    /// # Arguments
+    /// * `commands` - The commands to expand.
+    /// * `scope` - The files macro names are looked up in, in order. The first one is the file
+    ///   the commands come from.
+    fn expand_commands(
+        &mut self,
+        commands: Vec<Spanned<BraincrapCommand>>,
+        scope: &[PathBuf],
+    ) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+        let mut program = Vec::new();
+
+        for Spanned { node, span } in commands {
+            match node {
+                BraincrapCommand::DefineMacro {
+                    name,
+                    parameters,
+                    tokens,
+                    code,
+                } => {
+                    let module = scope[0].clone();
+                    if let Some(previous) = self.lookup(&module, &name, true, false) {
+                        self.warnings.push(BraincrapWarning::ShadowedMacro {
+                            name: name.clone(),
+                            span,
+                            previous: previous.span,
+                        });
+                    }
+                    let definition = Macro {
+                        name: name.clone(),
+                        module: module.clone(),
+                        parameters,
+                        tokens,
+                        code,
+                        span,
+                    };
+                    let module = self.modules.entry(module).or_default();
+                    module.macros.insert(name, definition);
+                }
+                BraincrapCommand::RunMacro { name } => {
+                    program.extend(self.expand_call(name, &[], span, scope)?);
+                }
+                BraincrapCommand::CallMacro { name, arguments } => {
+                    program.extend(self.expand_call(name, &arguments, span, scope)?);
+                }
+                BraincrapCommand::Import {
+                    path, alias, code, ..
+                } => {
+                    self.link(&scope[0], &path, alias.as_deref());
+                    self.collect(&code, &path);
+                    program.extend(self.expand_commands(code, &[path])?);
+                }
+                node => program.push(Spanned::new(node, span)),
+            }
+        }
+
+        Ok(program)
+    }
+
+    /// Expands a single macro call.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the macro.
+    /// * `arguments` - The arguments of the call.
+    /// * `span` - Where the call is written.
+    /// * `scope` - The files the call can see macros from, in order. Private macros are only
+    ///   looked up in the first one.
+    fn expand_call(
+        &mut self,
+        name: String,
+        arguments: &[Spanned<String>],
+        span: Span,
+        scope: &[PathBuf],
+    ) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+        // Private macros are only visible in the file the call is written in
+        let definition = scope
+            .iter()
+            .enumerate()
+            .find_map(|(depth, module)| self.lookup(module, &name, depth == 0, true))
+            .cloned();
+        let Some(definition) = definition else {
+            return Err(BraincrapError::UndefinedMacro { name, span });
+        };
+        let running = self
+            .stack
+            .iter()
+            .position(|(module, n, _)| *module == definition.module && *n == definition.name);
+        if let Some(start) = running {
+            let mut chain: Vec<String> = self.stack[start..]
+                .iter()
+                .map(|(_, _, called)| called.clone())
+                .collect();
+            chain.push(name);
+            return Err(BraincrapError::RecursiveMacro { chain, span });
+        }
+
+        let values = bind_arguments(&name, &definition.parameters, arguments, span)?;
+        let code = if definition.parameters.is_empty() {
+            definition.code
+        } else {
+            let tokens = substitute(&name, &definition.tokens, &values)?;
+            let pwd = self.imports.directory(&definition.module);
+            let mut parser = Parser::with_imports(&tokens, pwd, mem::take(&mut self.imports));
+            let code = parser.parse();
+            self.imports = parser.into_imports();
+            let code = code?;
+            match_loops(&code)?;
+            code
+        };
+
+        // The body sees its own file first, then whatever the caller sees
+        let mut inner_scope = vec![definition.module.clone()];
+        inner_scope.extend(scope.iter().cloned());
+
+        self.stack.push((definition.module, definition.name, name));
+        let program = self.expand_commands(code, &inner_scope);
+        self.stack.pop();
+        program
+    }
+}
+
+/// Returns `token` repeated `count` times, or the opposite command for a negative count.
+fn repeat(token: &BraincrapToken, count: i64) -> Option<BraincrapToken> {
+    let n = count.unsigned_abs() as usize;
+    match (token, count < 0) {
+        (BraincrapToken::Plus(_), false) | (BraincrapToken::Minus(_), true) => {
+            Some(BraincrapToken::Plus(n))
+        }
+        (BraincrapToken::Minus(_), false) | (BraincrapToken::Plus(_), true) => {
+            Some(BraincrapToken::Minus(n))
+        }
+        (BraincrapToken::Right(_), false) | (BraincrapToken::Left(_), true) => {
+            Some(BraincrapToken::Right(n))
+        }
+        (BraincrapToken::Left(_), false) | (BraincrapToken::Right(_), true) => {
+            Some(BraincrapToken::Left(n))
+        }
+        (BraincrapToken::Dot(_), false) => Some(BraincrapToken::Dot(n)),
+        (BraincrapToken::Comma(_), false) => Some(BraincrapToken::Comma(n)),
+        _ => None,
+    }
+}
+
+/// The largest repeat count a `%parameter` reference can expand to.
+const MAX_REPEAT_COUNT: u64 = u16::MAX as u64;
+
+/// Reads the value of `%parameter` as a repeat count.
+///
+/// # Errors
+/// Returns `BraincrapError::InvalidMacroArgument` if `value` is not a number or is further from
+/// 0 than `MAX_REPEAT_COUNT`.
+fn parse_repeat_count(
+    name: &str,
+    parameter: &str,
+    value: &str,
+    span: Span,
+) -> Result<i64, BraincrapError> {
+    let message = match value.parse::<i64>() {
+        Ok(count) if count.unsigned_abs() <= MAX_REPEAT_COUNT => return Ok(count),
+        Ok(_) => format!("{value} is larger than the largest repeat count {MAX_REPEAT_COUNT}"),
+        Err(_) => format!("{value:?} is not a valid repeat count for %{parameter}"),
+    };
+    Err(BraincrapError::InvalidMacroArgument {
+        name: name.to_string(),
+        message,
+        span,
+    })
+}
+
+/// Returns how many times a command token is repeated.
+fn repeat_count(token: &BraincrapToken) -> Option<usize> {
+    match token {
+        BraincrapToken::Plus(count)
+        | BraincrapToken::Minus(count)
+        | BraincrapToken::Left(count)
+        | BraincrapToken::Right(count)
+        | BraincrapToken::Dot(count)
+        | BraincrapToken::Comma(count) => Some(*count),
+        _ => None,
+    }
+}
+
+/// Replaces every `%parameter` token in a macro body with its argument.
+///
+/// A reference written directly after `+`, `-`, `<`, `>`, `.` or `,` is a repeat count: `>%n`
+/// with `n` set to 3 becomes `>>>`, and a negative count on `+`, `-`, `<` or `>` repeats the
+/// opposite command. Any other reference is replaced by the argument's code.
+///
+/// # Errors
+/// Returns `BraincrapError::InvalidMacroArgument` if a repeat count is not a number or is too
+/// large.
+fn substitute(
+    name: &str,
+    tokens: &[Spanned<BraincrapToken>],
+    values: &HashMap<String, Spanned<String>>,
+) -> Result<Vec<Spanned<BraincrapToken>>, BraincrapError> {
+    let mut substituted: Vec<Spanned<BraincrapToken>> = Vec::new();
+    // Whether the last token came from the body rather than from an argument
+    let mut from_body = false;
+
+    for token in tokens {
+        if let BraincrapToken::Arguments(arguments) = &token.node {
+            let arguments = arguments
+                .iter()
+                .map(|argument| substitute_argument(name, argument, values))
+                .collect::<Result<_, _>>()?;
+            substituted.push(Spanned::new(
+                BraincrapToken::Arguments(arguments),
+                token.span,
+            ));
+            from_body = true;
+            continue;
+        }
+        let BraincrapToken::Parameter(parameter) = &token.node else {
+            substituted.push(token.clone());
+            from_body = true;
+            continue;
+        };
+        let (value, value_span) = values
+            .get(parameter)
+            .map_or(("", token.span), |value| (value.node.as_str(), value.span));
+
+        let previous = substituted
+            .last()
+            .filter(|previous| from_body && previous.span.end == token.span.start)
+            .and_then(|previous| Some((previous.clone(), repeat_count(&previous.node)?)));
+        from_body = false;
+        let Some((previous, count)) = previous else {
+            let mut lexer = Lexer::with_origin(value.to_string(), value_span);
+            substituted.extend(lexer.tokenize()?);
+            continue;
+        };
+
+        let n = parse_repeat_count(name, parameter, value, value_span)?;
+        let Some(repeated) = repeat(&previous.node, n) else {
+            return Err(BraincrapError::InvalidMacroArgument {
+                name: name.to_string(),
+                message: format!("{value:?} is not a valid repeat count for %{parameter}"),
+                span: value_span,
+            });
+        };
+        substituted.pop();
+        if count > 1 {
+            substituted.push(Spanned::new(
+                repeat(&previous.node, (count - 1) as i64).unwrap_or(previous.node),
+                previous.span,
+            ));
+        }
+        if repeat_count(&repeated) != Some(0) {
+            substituted.push(Spanned::new(repeated, previous.span.to(token.span)));
+        }
+    }
+
+    Ok(substituted)
+}
+
+/// Replaces every `%parameter` reference in the text of a nested call's argument, so that
+/// `@inner(%n)` passes the value of `n` on to `inner`.
+///
+/// References are treated the same way as in the body: one written directly after a command
+/// character is a repeat count, and any other is replaced by the argument's code.
+///
+/// # Errors
+/// Returns `BraincrapError::InvalidMacroArgument` if a repeat count is not a number or is too
+/// large.
+fn substitute_argument(
+    name: &str,
+    argument: &Spanned<String>,
+    values: &HashMap<String, Spanned<String>>,
+) -> Result<Spanned<String>, BraincrapError> {
+    let mut substituted = String::new();
+    // Whether the last character came from the argument rather than from a value
+    let mut from_argument = false;
+    let mut chars = argument.node.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' || !chars.peek().is_some_and(|&c| is_name_char(c)) {
+            substituted.push(c);
+            from_argument = true;
+            continue;
+        }
+        let mut parameter = String::new();
+        while let Some(c) = chars.next_if(|&c| is_name_char(c)) {
+            parameter.push(c);
+        }
+        let (value, value_span) = values.get(&parameter).map_or(("", argument.span), |value| {
+            (value.node.as_str(), value.span)
+        });
+
+        let previous = substituted
+            .chars()
+            .last()
+            .filter(|_| from_argument)
+            .and_then(|c| Some((c, command_token(c)?)));
+        from_argument = false;
+        let Some((previous, command)) = previous else {
+            substituted.push_str(value);
+            continue;
+        };
+
+        let count = parse_repeat_count(name, &parameter, value, value_span)?;
+        let Some(repeated) = repeat(&command, count) else {
+            return Err(BraincrapError::InvalidMacroArgument {
+                name: name.to_string(),
+                message: format!("{value:?} is not a valid repeat count for %{parameter}"),
+                span: value_span,
+            });
+        };
+        substituted.pop();
+        let command = match repeated {
+            BraincrapToken::Plus(_) => '+',
+            BraincrapToken::Minus(_) => '-',
+            BraincrapToken::Left(_) => '<',
+            BraincrapToken::Right(_) => '>',
+            _ => previous,
+        };
+        substituted.extend(std::iter::repeat_n(command, count.unsigned_abs() as usize));
+    }
+
+    Ok(Spanned::new(substituted, argument.span))
+}
+
+/// Returns the single command token written as `c`, if `c` is a command character.
+fn command_token(c: char) -> Option<BraincrapToken> {
+    match c {
+        '+' => Some(BraincrapToken::Plus(1)),
+        '-' => Some(BraincrapToken::Minus(1)),
+        '<' => Some(BraincrapToken::Left(1)),
+        '>' => Some(BraincrapToken::Right(1)),
+        '.' => Some(BraincrapToken::Dot(1)),
+        ',' => Some(BraincrapToken::Comma(1)),
+        _ => None,
+    }
+}
@@ -0,0 +1,219 @@
+use crate::error::BraincrapError;
+use crate::expander::Expander;
+use crate::ir::{lower, Instruction};
+use crate::optimizer::PassManager;
+use crate::parser::BraincrapCommand;
+use crate::span::{Span, Spanned};
+use crate::tape::{EofBehavior, TapeConfig};
+use std::io::{Read, Write};
+
+/// An interpreter that executes Braincrap commands directly against a tape.
+pub struct Interpreter<R: Read, W: Write> {
+    /// The memory tape. Every cell stays within the range described by `config`.
+    tape: Vec<i64>,
+    /// Cell width, overflow and EOF semantics.
+    config: TapeConfig,
+    /// The index of the current cell.
+    pointer: usize,
+    /// Where `,` reads bytes from.
+    input: R,
+    /// Where `.` writes bytes to.
+    output: W,
+    /// Expands macros and imports before running.
+    expander: Expander,
+    /// The optimizations run before executing a program.
+    passes: PassManager,
+    /// Whether nothing ran on the tape yet, so the optimizations may assume it is all 0.
+    fresh: bool,
+}
+
+impl<R: Read, W: Write> Interpreter<R, W> {
+    /// Creates a new `Interpreter` instance with the default tape configuration.
+    ///
+    /// # Arguments
+    /// * `input` - The reader used by `Input` commands.
+    /// * `output` - The writer used by `Output` commands.
+    pub fn new(input: R, output: W) -> Self {
+        Self::with_config(input, output, TapeConfig::default())
+    }
+
+    /// Creates a new `Interpreter` instance with a zeroed tape described by `config`.
+    ///
+    /// # Arguments
+    /// * `input` - The reader used by `Input` commands.
+    /// * `output` - The writer used by `Output` commands.
+    /// * `config` - The tape semantics to honour.
+    pub fn with_config(input: R, output: W, config: TapeConfig) -> Self {
+        Self {
+            tape: vec![0; config.tape_length],
+            config,
+            pointer: 0,
+            input,
+            output,
+            expander: Expander::new(),
+            passes: PassManager::default(),
+            fresh: true,
+        }
+    }
+
+    /// Sets the optimizations `run` applies before executing a program.
+    pub fn set_passes(&mut self, passes: PassManager) {
+        self.passes = passes;
+    }
+
+    /// Returns the current state of the tape.
+    pub fn tape(&self) -> &[i64] {
+        &self.tape
+    }
+
+    /// Returns the index of the current cell.
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Expands macros and imports, then executes the resulting program.
+    ///
+    /// Every run continues on the tape and pointer left behind by the previous one.
+    ///
+    /// # Errors
+    /// Returns an error if the program is malformed, leaves the tape, overflows a cell with
+    /// `Overflow::Error`, or reading input or writing output fails.
+    pub fn run(&mut self, commands: Vec<Spanned<BraincrapCommand>>) -> Result<(), BraincrapError> {
+        let program = self.expander.expand(commands)?;
+        let program = if self.fresh {
+            self.passes.run(lower(&program)?, &self.config)
+        } else {
+            self.passes.run_resumed(lower(&program)?, &self.config)
+        };
+        self.execute(&program)
+    }
+
+    /// Executes already lowered instructions, starting from the current cell.
+    ///
+    /// # Errors
+    /// Returns an error if the program leaves the tape, overflows a cell with `Overflow::Error`,
+    /// or reading input or writing output fails.
+    pub fn execute(&mut self, program: &[Spanned<Instruction>]) -> Result<(), BraincrapError> {
+        self.fresh = false;
+        self.execute_block(program)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    /// Executes a program or loop body for `execute`.
+    fn execute_block(&mut self, program: &[Spanned<Instruction>]) -> Result<(), BraincrapError> {
+        for instruction in program {
+            let span = instruction.span;
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    let cell = self.cell(*offset, span)?;
+                    self.add(cell, *delta, span)?;
+                }
+                Instruction::Move(distance) => self.pointer = self.cell(*distance, span)?,
+                Instruction::SetZero { offset } => {
+                    let cell = self.cell(*offset, span)?;
+                    self.tape[cell] = 0;
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    // A loop that never ran did not touch its destination, which may well be
+                    // outside of the tape
+                    let src = self.cell(*src, span)?;
+                    if self.tape[src] == 0 {
+                        continue;
+                    }
+                    let dst = self.cell(*dst, span)?;
+                    self.tape[dst] = self
+                        .config
+                        .multiply_add(self.tape[dst], self.tape[src], *factor)
+                        .ok_or(BraincrapError::CellOverflow { pointer: dst, span })?;
+                }
+                Instruction::Scan { step } => self.scan(*step, span)?,
+                Instruction::Loop { body } => {
+                    while self.tape[self.pointer] != 0 {
+                        self.execute_block(body)?;
+                    }
+                }
+                Instruction::Output { offset, count } => {
+                    let cell = self.cell(*offset, span)?;
+                    for _ in 0..*count {
+                        // Only the lowest byte of wider cells is written
+                        self.output.write_all(&[self.tape[cell] as u8])?;
+                    }
+                }
+                Instruction::Input { offset, count } => {
+                    let cell = self.cell(*offset, span)?;
+                    // A buffered prompt has to be shown before waiting for the answer
+                    self.output.flush()?;
+                    for _ in 0..*count {
+                        self.read_cell(cell)?;
+                    }
+                }
+                Instruction::Print { bytes } => self.output.write_all(bytes)?,
+                Instruction::Init { cells } => {
+                    let end = self.cell(cells.len() as isize - 1, span)?;
+                    self.tape[self.pointer..=end].copy_from_slice(cells);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the pointer by `step` until it reaches a cell that is 0.
+    ///
+    /// Searches the tape with an iterator instead of checking the bounds at every step.
+    fn scan(&mut self, step: isize, span: Span) -> Result<(), BraincrapError> {
+        let distance = step.unsigned_abs();
+        let (found, checked) = if step > 0 {
+            let cells = self.tape[self.pointer..].iter().step_by(distance);
+            (cells.clone().position(|&c| c == 0), cells.len())
+        } else {
+            let cells = self.tape[..=self.pointer].iter().rev().step_by(distance);
+            (cells.clone().position(|&c| c == 0), cells.len())
+        };
+        // Without a zero the scan leaves the tape right after the last cell it checked
+        let steps = found.unwrap_or(checked);
+        let pointer = self.pointer as isize + steps as isize * step;
+        if found.is_none() {
+            return Err(BraincrapError::PointerOutOfBounds { pointer, span });
+        }
+        self.pointer = pointer as usize;
+        Ok(())
+    }
+
+    /// Returns the index of the cell `offset` cells away from the pointer.
+    fn cell(&self, offset: isize, span: Span) -> Result<usize, BraincrapError> {
+        let pointer = self.pointer as isize + offset;
+        if pointer < 0 || pointer as usize >= self.tape.len() {
+            return Err(BraincrapError::PointerOutOfBounds { pointer, span });
+        }
+        Ok(pointer as usize)
+    }
+
+    /// Adds `delta` to the cell at `index` according to the overflow behavior.
+    fn add(&mut self, index: usize, delta: i64, span: Span) -> Result<(), BraincrapError> {
+        let cell = &mut self.tape[index];
+        *cell = self
+            .config
+            .add(*cell, delta)
+            .ok_or(BraincrapError::CellOverflow {
+                pointer: index,
+                span,
+            })?;
+        Ok(())
+    }
+
+    /// Reads a single byte into the cell at `index`, honouring the EOF behavior.
+    fn read_cell(&mut self, index: usize) -> Result<(), BraincrapError> {
+        let mut buffer = [0u8; 1];
+        let value = match self.input.read(&mut buffer)? {
+            0 => match self.config.eof {
+                EofBehavior::Zero => 0,
+                EofBehavior::MinusOne => self.config.wrap(-1),
+                EofBehavior::Unchanged => return Ok(()),
+            },
+            _ => self.config.wrap(i128::from(buffer[0])),
+        };
+        self.tape[index] = value;
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::BraincrapError;
+use crate::parser::BraincrapCommand;
+use crate::span::{Span, Spanned};
+use std::fmt;
+use std::mem;
+
+/// A lowered instruction shared by every backend and the interpreter.
+///
+/// Offsets are relative to the current cell, so `Add { offset: 2, delta: 1 }` is `ptr[2] += 1`.
+/// Loops form a tree instead of matching `OpenLoop`/`CloseLoop` pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// Adds `delta` to the cell at `offset`.
+    Add { offset: isize, delta: i64 },
+    /// Moves the pointer by the given number of cells, to the left if negative.
+    Move(isize),
+    /// Sets the cell at `offset` to 0.
+    SetZero { offset: isize },
+    /// Adds the cell at `src` multiplied by `factor` to the cell at `dst`.
+    ///
+    /// Always followed by a `SetZero` of `src`, together they replace a loop such as `[->++<]`.
+    MulAdd { src: isize, dst: isize, factor: i64 },
+    /// Moves the pointer by `step` until it reaches a cell that is 0, like `[>]` or `[<<]`.
+    Scan { step: isize },
+    /// Runs `body` while the current cell is not 0.
+    Loop { body: Vec<Spanned<Instruction>> },
+    /// Writes the cell at `offset` `count` times.
+    Output { offset: isize, count: usize },
+    /// Reads `count` bytes into the cell at `offset`, keeping the last one.
+    Input { offset: isize, count: usize },
+    /// Writes `bytes`, the output of a part of the program that was run at compile time.
+    ///
+    /// Only found at the start of a program, where the current cell is still 0.
+    Print { bytes: Vec<u8> },
+    /// Stores `cells` on the tape starting at the current cell, the tape left behind by a part
+    /// of the program that was run at compile time.
+    ///
+    /// Only found at the start of a program, where the tape is still all 0.
+    Init { cells: Vec<i64> },
+}
+
+/// Lowers an expanded program into a tree of `Instruction`s.
+///
+/// Every instruction keeps the span of the command it came from, a loop takes the span of its
+/// `[`.
+///
+/// # Arguments
+/// * `program` - A program without macros or imports, as returned by `Expander::expand`.
+///
+/// # Errors
+/// Returns `BraincrapError::UnbalancedBrackets` if the loops do not match.
+pub fn lower(
+    program: &[Spanned<BraincrapCommand>],
+) -> Result<Vec<Spanned<Instruction>>, BraincrapError> {
+    let mut current: Vec<Spanned<Instruction>> = Vec::new();
+    // The enclosing instruction lists and the span of the `[` that left each of them
+    let mut stack: Vec<(Vec<Spanned<Instruction>>, Span)> = Vec::new();
+
+    for command in program {
+        let span = command.span;
+        let instruction = match command.node {
+            BraincrapCommand::Addition(count) => Instruction::Add {
+                offset: 0,
+                delta: count as i64,
+            },
+            BraincrapCommand::Substraction(count) => Instruction::Add {
+                offset: 0,
+                delta: -(count as i64),
+            },
+            BraincrapCommand::MoveLeft(count) => Instruction::Move(-(count as isize)),
+            BraincrapCommand::MoveRight(count) => Instruction::Move(count as isize),
+            BraincrapCommand::Output(count) => Instruction::Output { offset: 0, count },
+            BraincrapCommand::Input(count) => Instruction::Input { offset: 0, count },
+            BraincrapCommand::OpenLoop => {
+                stack.push((mem::take(&mut current), span));
+                continue;
+            }
+            BraincrapCommand::CloseLoop => {
+                let (outer, open) = stack
+                    .pop()
+                    .ok_or(BraincrapError::UnbalancedBrackets { bracket: ']', span })?;
+                let body = mem::replace(&mut current, outer);
+                current.push(Spanned::new(Instruction::Loop { body }, open));
+                continue;
+            }
+            // `expand` leaves no macros or imports behind
+            BraincrapCommand::DefineMacro { .. }
+            | BraincrapCommand::RunMacro { .. }
+            | BraincrapCommand::CallMacro { .. }
+            | BraincrapCommand::Import { .. } => continue,
+        };
+        current.push(Spanned::new(instruction, span));
+    }
+
+    match stack.first() {
+        None => Ok(current),
+        Some(&(_, span)) => Err(BraincrapError::UnbalancedBrackets { bracket: '[', span }),
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Writes the instruction on a single line, a loop is written without its body.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Add { offset, delta } if *delta < 0 => {
+                write!(f, "[{offset}] -= {}", -delta)
+            }
+            Instruction::Add { offset, delta } => write!(f, "[{offset}] += {delta}"),
+            Instruction::Move(distance) => write!(f, "move {distance}"),
+            Instruction::SetZero { offset } => write!(f, "[{offset}] = 0"),
+            Instruction::MulAdd { src, dst, factor } => write!(f, "[{dst}] += [{src}] * {factor}"),
+            Instruction::Scan { step } => write!(f, "scan {step}"),
+            Instruction::Loop { .. } => write!(f, "loop"),
+            Instruction::Output { offset, count } => write!(f, "output [{offset}] x{count}"),
+            Instruction::Input { offset, count } => write!(f, "input [{offset}] x{count}"),
+            Instruction::Print { bytes } => write!(f, "print {:?}", String::from_utf8_lossy(bytes)),
+            Instruction::Init { cells } => write!(f, "init {cells:?}"),
+        }
+    }
+}
+
+/// Writes a program one instruction per line, with loop bodies indented and the line and column
+/// each instruction came from.
+pub fn dump(program: &[Spanned<Instruction>]) -> String {
+    let mut output = String::new();
+    dump_block(program, 0, &mut output);
+    output
+}
+
+/// Writes the instructions of a program or loop body at the given depth for `dump`.
+fn dump_block(program: &[Spanned<Instruction>], depth: usize, output: &mut String) {
+    for instruction in program {
+        let line = format!("{}{}", "    ".repeat(depth), instruction.node);
+        match instruction.span.line {
+            0 => output.push_str(&line),
+            _ => output.push_str(&format!(
+                "{line:<32} ; {}:{}",
+                instruction.span.line, instruction.span.column
+            )),
+        }
+        output.push('\n');
+        if let Instruction::Loop { body } = &instruction.node {
+            dump_block(body, depth + 1, output);
+            output.push_str(&format!("{}end\n", "    ".repeat(depth)));
+        }
+    }
+}
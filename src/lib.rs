@@ -1,5 +1,13 @@
 #![allow(unexpected_cfgs)]
 #![cfg(not(test))]
+pub mod error;
+pub mod expander;
+pub mod interpreter;
+pub mod ir;
+pub mod optimizer;
 pub mod parser;
+pub mod span;
+pub mod stdlib;
+pub mod tape;
 pub mod tokenizer;
 pub mod transpiler;
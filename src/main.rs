@@ -9,16 +9,28 @@
 #![warn(clippy::expect_used)]
 #![allow(unexpected_cfgs)]
 #![cfg(not(test))]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::expander::Expander;
+use braincrap_rs::interpreter::Interpreter;
+use braincrap_rs::ir::{dump, lower, Instruction};
+use braincrap_rs::optimizer::PassManager;
 use braincrap_rs::parser::{BraincrapCommand, Parser as BraincrapParser};
+use braincrap_rs::span::{SourceMap, Spanned};
+use braincrap_rs::tape::{EofBehavior, Overflow, TapeConfig};
 use braincrap_rs::tokenizer;
 use braincrap_rs::transpiler::{Transpiler, TranspilerArguments};
 use clap::Parser;
 use env_logger::Builder;
 use log::debug;
+use std::env;
 use std::fs;
+use std::io::{self, BufWriter};
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::process;
 
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// Path to the input Braincrap file
@@ -30,30 +42,148 @@ struct Args {
     output: Option<String>,
 
     /// Transpile into Brainfuck
-    #[clap(short = 'b', long = "brainfuck", conflicts_with = "c", action)]
+    #[clap(short = 'b', long = "brainfuck", conflicts_with_all = ["c", "rust", "wat", "asm", "llvm", "js", "python", "run"], action)]
     brainfuck: bool,
 
     /// Transpile into C
-    #[clap(short = 'c', long = "C", conflicts_with = "brainfuck", action)]
+    #[clap(short = 'c', long = "C", conflicts_with_all = ["brainfuck", "rust", "wat", "asm", "llvm", "js", "python", "run"], action)]
     c: bool,
+
+    /// Transpile into a standalone Rust program
+    #[clap(short = 'R', long = "rust", conflicts_with_all = ["brainfuck", "c", "wat", "asm", "llvm", "js", "python", "run"], action)]
+    rust: bool,
+
+    /// Transpile into a WebAssembly text module
+    #[clap(short = 'w', long = "wat", conflicts_with_all = ["brainfuck", "c", "rust", "asm", "llvm", "js", "python", "run"], action)]
+    wat: bool,
+
+    /// Transpile into x86-64 assembly for Linux, assembled with `as` and linked with `ld`
+    #[clap(short = 'a', long = "asm", conflicts_with_all = ["brainfuck", "c", "rust", "wat", "llvm", "js", "python", "run"], action)]
+    asm: bool,
+
+    /// Transpile into LLVM IR, which can be compiled with `clang` or `llc`
+    #[clap(short = 'l', long = "llvm", conflicts_with_all = ["brainfuck", "c", "rust", "wat", "asm", "js", "python", "run"], action)]
+    llvm: bool,
+
+    /// Transpile into a JavaScript `run(inputString)` function for browsers and Node
+    #[clap(short = 'j', long = "js", conflicts_with_all = ["brainfuck", "c", "rust", "wat", "asm", "llvm", "python", "run"], action)]
+    js: bool,
+
+    /// Make the JavaScript runnable with Node, reading stdin and writing stdout
+    #[clap(long = "node", requires = "js", action)]
+    node: bool,
+
+    /// Transpile into a Python 3 script
+    #[clap(short = 'p', long = "python", conflicts_with_all = ["brainfuck", "c", "rust", "wat", "asm", "llvm", "js", "run"], action)]
+    python: bool,
+
+    /// Run the program with the built-in interpreter
+    #[clap(short = 'r', long = "run", conflicts_with_all = ["brainfuck", "c", "rust", "wat", "asm", "llvm", "js", "python"], action)]
+    run: bool,
+
+    /// Add a directory to search for library imports such as `$std.bf`.
+    /// Searched before the directories in `BRAINCRAP_PATH`
+    #[clap(short = 'I', long = "include", value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+
+    /// Expand a file every time it is imported instead of only the first time
+    #[clap(long = "no-include-once", action)]
+    no_include_once: bool,
+
+    /// Width of a tape cell in bits (8, 16 or 32)
+    #[clap(long = "cell-bits", default_value_t = 8)]
+    cell_bits: u32,
+
+    /// Use signed cells
+    #[clap(long = "signed", action)]
+    signed: bool,
+
+    /// What happens when a cell overflows (wrap, saturate or error)
+    #[clap(long = "overflow", default_value_t = Overflow::Wrap)]
+    overflow: Overflow,
+
+    /// Number of cells on the tape
+    #[clap(long = "tape-length", default_value_t = 30000)]
+    tape_length: usize,
+
+    /// What input stores on EOF (0, -1 or unchanged)
+    #[clap(long = "eof", default_value_t = EofBehavior::MinusOne, allow_hyphen_values = true)]
+    eof: EofBehavior,
+
+    /// Optimization level, from 0 (every command is kept as written) to 3 (every pass)
+    #[clap(
+        short = 'O',
+        long = "opt-level",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u8).range(0..=i64::from(PassManager::MAX_LEVEL))
+    )]
+    opt_level: u8,
+
+    /// How many instructions -O3 may run at compile time before leaving the rest for run time
+    #[clap(long = "step-budget", default_value_t = PassManager::DEFAULT_STEP_BUDGET)]
+    step_budget: u64,
+
+    /// Print the program to stderr after lowering it and after every optimization pass
+    #[clap(long = "emit-passes", action)]
+    emit_passes: bool,
 }
 
 fn main() {
     let args = Args::parse();
 
     let transpiler_arg = if args.brainfuck {
-        TranspilerArguments::Brainfuck
+        Some(TranspilerArguments::Brainfuck)
     } else if args.c {
-        TranspilerArguments::C
+        Some(TranspilerArguments::C)
+    } else if args.rust {
+        Some(TranspilerArguments::Rust)
+    } else if args.wat {
+        Some(TranspilerArguments::Wat)
+    } else if args.asm {
+        Some(TranspilerArguments::Asm)
+    } else if args.llvm {
+        Some(TranspilerArguments::Llvm)
+    } else if args.js {
+        Some(TranspilerArguments::Js)
+    } else if args.python {
+        Some(TranspilerArguments::Python)
+    } else if args.run {
+        None
     } else {
-        eprintln!("One of -b or -c must be specified!");
-        return;
+        eprintln!("One of -b, -c, -R, -w, -a, -l, -j, -p or -r must be specified!");
+        process::exit(1);
     };
 
+    let tape = TapeConfig {
+        cell_bits: args.cell_bits,
+        signed: args.signed,
+        overflow: args.overflow,
+        tape_length: args.tape_length,
+        eof: args.eof,
+    };
+    if let Err(e) = tape.validate() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+
     // Logging initalization. Set RUST_LOG in the shell
     let env = env_logger::Env::default().filter_or("RUST_LOG", "debug");
     Builder::from_env(env).init();
 
+    let mut sources = SourceMap::new();
+    if let Err(e) = compile(&args, transpiler_arg, tape, &mut sources) {
+        eprintln!("{}", sources.render(&e));
+        process::exit(1);
+    }
+}
+
+/// Runs the whole pipeline on the input file, either transpiling or interpreting it.
+fn compile(
+    args: &Args,
+    transpiler_arg: Option<TranspilerArguments>,
+    tape: TapeConfig,
+    sources: &mut SourceMap,
+) -> Result<(), BraincrapError> {
     // Get the input and pwd
     let input_path = Path::new(&args.input);
     let pwd: PathBuf = input_path
@@ -62,35 +192,103 @@ fn main() {
         .to_path_buf();
 
     // Read the file
-    let input = fs::read_to_string(input_path)
-        .unwrap_or_else(|_| panic!("Failed to read file: {}", input_path.display()));
+    let input = fs::read_to_string(input_path).map_err(|_| BraincrapError::ImportNotFound {
+        path: input_path.to_path_buf(),
+        span: None,
+    })?;
+    let file = sources.add(input_path.to_path_buf(), input.clone());
 
-    let mut tokenizer = tokenizer::Lexer::new(input);
-    let tokens = tokenizer.tokenize();
+    let mut tokenizer = tokenizer::Lexer::with_file(input, file);
+    let tokens = tokenizer.tokenize()?;
     debug!("Tokenized: {tokens:?}");
 
-    let mut parser = BraincrapParser::new(&tokens, pwd);
-    let commands: Vec<BraincrapCommand> = parser.parse();
-    debug!("Parsed: {commands:?}");
+    // The parser and the expander register imported files, the source map is handed back even
+    // if either of them fails
+    let mut parser = BraincrapParser::with_sources(&tokens, pwd, mem::take(sources));
+    parser.set_file(input_path);
+    parser.set_include_once(!args.no_include_once);
+    parser.set_include_dirs(include_dirs(args));
+    let parsed = parser.parse();
+    if let Ok(commands) = &parsed {
+        debug!("Parsed: {commands:?}");
+    }
+
+    // Macro bodies are parsed again while expanding, with the same imports as the program
+    let mut expander = Expander::with_imports(parser.into_imports());
+    let commands = parsed.and_then(|commands| expander.expand(commands));
+    for warning in expander.warnings() {
+        eprintln!("{}", expander.sources().render_warning(warning));
+    }
+    *sources = expander.into_sources();
+    let commands: Vec<Spanned<BraincrapCommand>> = commands?;
 
-    let mut transpiler = Transpiler::new();
-    let mut transpiled_code = transpiler.transpile(commands, &transpiler_arg);
+    let program = lower(&commands)?;
+    if args.emit_passes {
+        eprintln!("; lower\n{}", dump(&program));
+    }
+    let mut passes = PassManager::with_level(args.opt_level);
+    passes.set_step_budget(args.step_budget);
+    let program = passes.run_with(program, &tape, |pass, program| {
+        if args.emit_passes {
+            eprintln!("; {pass}\n{}", dump(program));
+        }
+    });
+
+    let Some(transpiler_arg) = transpiler_arg else {
+        return run(&program, tape, args.output.as_deref());
+    };
+
+    let transpiler = Transpiler::with_config(tape);
+    let mut transpiled_code = transpiler.transpile_instructions(&program, &transpiler_arg)?;
     debug!("Transpiled: {transpiled_code}");
 
-    if let TranspilerArguments::C = transpiler_arg {
-        let c_start_setup = "#include <stdio.h>\nint main() {\n\tunsigned char tape[30000] = {0};\n\tunsigned char *ptr = tape;\n\n";
+    match transpiler_arg {
+        TranspilerArguments::C => transpiled_code = transpiler.c_program(&transpiled_code),
+        TranspilerArguments::Rust => transpiled_code = transpiler.rust_program(&transpiled_code),
+        TranspilerArguments::Wat => transpiled_code = transpiler.wat_program(&transpiled_code),
+        TranspilerArguments::Asm => transpiled_code = transpiler.asm_program(&transpiled_code),
+        TranspilerArguments::Llvm => transpiled_code = transpiler.llvm_program(&transpiled_code),
+        TranspilerArguments::Js if args.node => {
+            transpiled_code = transpiler.node_program(&transpiled_code);
+        }
+        TranspilerArguments::Js => transpiled_code = transpiler.js_program(&transpiled_code),
+        TranspilerArguments::Python => {
+            transpiled_code = transpiler.python_program(&transpiled_code);
+        }
+        TranspilerArguments::Brainfuck => {}
+    }
 
-        let c_end_setup = "\n\treturn 0;\n}\n";
+    if let Some(output_path) = &args.output {
+        fs::write(output_path, &transpiled_code)?;
+    } else {
+        println!("{transpiled_code}");
+    }
 
-        transpiled_code = format!("{c_start_setup}\t{transpiled_code}{c_end_setup}");
+    Ok(())
+}
+
+/// Collects the library import directories from `-I`, followed by those in `BRAINCRAP_PATH`.
+fn include_dirs(args: &Args) -> Vec<PathBuf> {
+    let mut dirs = args.include_dirs.clone();
+    if let Some(path) = env::var_os("BRAINCRAP_PATH") {
+        dirs.extend(env::split_paths(&path).filter(|dir| !dir.as_os_str().is_empty()));
     }
+    dirs
+}
 
-    // Value not in scope now, old one is used
+/// Executes the program with the built-in interpreter, reading from stdin and
+/// writing either to the output file or to stdout.
+fn run(
+    program: &[Spanned<Instruction>],
+    tape: TapeConfig,
+    output_path: Option<&str>,
+) -> Result<(), BraincrapError> {
+    let stdin = io::stdin().lock();
 
-    if let Some(output_path) = &args.output {
-        fs::write(output_path, &transpiled_code)
-            .unwrap_or_else(|_| panic!("Failed to write to output file: {output_path}",));
+    if let Some(output_path) = output_path {
+        let file = fs::File::create(output_path)?;
+        Interpreter::with_config(stdin, BufWriter::new(file), tape).execute(program)
     } else {
-        println!("{transpiled_code}",);
+        Interpreter::with_config(stdin, BufWriter::new(io::stdout().lock()), tape).execute(program)
     }
 }
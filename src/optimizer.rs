@@ -0,0 +1,598 @@
+use crate::ir::Instruction;
+use crate::span::{Span, Spanned};
+use crate::tape::{Overflow, TapeConfig};
+use std::{fmt, iter, slice};
+
+/// A single optimization pass over lowered instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// Cancels neighbouring instructions and removes dead loops, see `peephole`.
+    Peephole,
+    /// Turns clear and multiply loops into assignments, see `simplify_loops`.
+    SimplifyLoops,
+    /// Turns loops that only move into scans, see `simplify_scans`.
+    SimplifyScans,
+    /// Folds pointer moves into offsets, see `fold_offsets`.
+    FoldOffsets,
+    /// Runs the start of the program at compile time, see `evaluate_prefix`.
+    EvaluatePrefix { budget: u64 },
+}
+
+impl Pass {
+    /// Runs the pass over a program.
+    pub fn run(
+        self,
+        program: Vec<Spanned<Instruction>>,
+        tape: &TapeConfig,
+    ) -> Vec<Spanned<Instruction>> {
+        match self {
+            Pass::Peephole => peephole(program, tape),
+            Pass::SimplifyLoops => simplify_loops(program, tape),
+            Pass::SimplifyScans => simplify_scans(program),
+            Pass::FoldOffsets => fold_offsets(program),
+            Pass::EvaluatePrefix { budget } => evaluate_prefix(program, tape, budget),
+        }
+    }
+
+    /// Runs the pass over a program that starts on the tape left behind by an earlier one,
+    /// without assuming that the tape is all 0.
+    pub fn run_resumed(
+        self,
+        program: Vec<Spanned<Instruction>>,
+        tape: &TapeConfig,
+    ) -> Vec<Spanned<Instruction>> {
+        match self {
+            Pass::Peephole => peephole_block(program, tape, false),
+            Pass::EvaluatePrefix { .. } => program,
+            _ => self.run(program, tape),
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pass::Peephole => write!(f, "peephole"),
+            Pass::SimplifyLoops => write!(f, "simplify-loops"),
+            Pass::SimplifyScans => write!(f, "simplify-scans"),
+            Pass::FoldOffsets => write!(f, "fold-offsets"),
+            Pass::EvaluatePrefix { .. } => write!(f, "evaluate-prefix"),
+        }
+    }
+}
+
+/// Runs a list of passes in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassManager {
+    passes: Vec<Pass>,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::with_level(2)
+    }
+}
+
+impl PassManager {
+    /// The highest optimization level.
+    pub const MAX_LEVEL: u8 = 3;
+
+    /// How many instructions `Pass::EvaluatePrefix` runs at compile time by default.
+    pub const DEFAULT_STEP_BUDGET: u64 = 1_000_000;
+
+    /// Creates a new `PassManager` that runs no passes.
+    pub fn new() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    /// Creates a new `PassManager` with the passes of an optimization level.
+    ///
+    /// * `0` runs nothing, every instruction maps to a command in the source.
+    /// * `1` cancels and merges neighbouring instructions and removes dead loops.
+    /// * `2` also recognises clear, multiply and scan loops, and folds pointer moves into
+    ///   offsets.
+    /// * `3` and above also run the program up to its first input at compile time.
+    pub fn with_level(level: u8) -> Self {
+        let mut passes = match level {
+            0 => vec![],
+            1 => vec![Pass::Peephole],
+            _ => vec![
+                Pass::Peephole,
+                Pass::SimplifyLoops,
+                Pass::SimplifyScans,
+                Pass::FoldOffsets,
+            ],
+        };
+        if level >= 3 {
+            passes.push(Pass::EvaluatePrefix {
+                budget: Self::DEFAULT_STEP_BUDGET,
+            });
+        }
+        Self { passes }
+    }
+
+    /// Sets how many instructions `Pass::EvaluatePrefix` may run at compile time.
+    pub fn set_step_budget(&mut self, budget: u64) {
+        for pass in &mut self.passes {
+            if let Pass::EvaluatePrefix { budget: current } = pass {
+                *current = budget;
+            }
+        }
+    }
+
+    /// Adds a pass to run after the others.
+    pub fn add(&mut self, pass: Pass) {
+        self.passes.push(pass);
+    }
+
+    /// Returns the passes in the order they run.
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    /// Runs every pass over a lowered program.
+    ///
+    /// # Arguments
+    /// * `program` - The instructions returned by `ir::lower`.
+    /// * `tape` - The tape semantics the result has to preserve.
+    pub fn run(
+        &self,
+        program: Vec<Spanned<Instruction>>,
+        tape: &TapeConfig,
+    ) -> Vec<Spanned<Instruction>> {
+        self.run_with(program, tape, |_, _| {})
+    }
+
+    /// Runs every pass over a lowered program that starts on the tape left behind by an earlier
+    /// one, see `Pass::run_resumed`.
+    pub fn run_resumed(
+        &self,
+        mut program: Vec<Spanned<Instruction>>,
+        tape: &TapeConfig,
+    ) -> Vec<Spanned<Instruction>> {
+        for &pass in &self.passes {
+            program = pass.run_resumed(program, tape);
+        }
+        program
+    }
+
+    /// Runs every pass over a lowered program, calling `inspect` with the result of each one.
+    pub fn run_with(
+        &self,
+        mut program: Vec<Spanned<Instruction>>,
+        tape: &TapeConfig,
+        mut inspect: impl FnMut(Pass, &[Spanned<Instruction>]),
+    ) -> Vec<Spanned<Instruction>> {
+        for &pass in &self.passes {
+            program = pass.run(program, tape);
+            inspect(pass, &program);
+        }
+        program
+    }
+}
+
+/// Runs every optimization pass over a lowered program.
+///
+/// # Arguments
+/// * `program` - The instructions returned by `ir::lower`.
+/// * `tape` - The tape semantics the result has to preserve.
+pub fn optimize(
+    program: Vec<Spanned<Instruction>>,
+    tape: &TapeConfig,
+) -> Vec<Spanned<Instruction>> {
+    PassManager::with_level(PassManager::MAX_LEVEL).run(program, tape)
+}
+
+/// Cancels and merges adjacent additions and pointer moves, and removes loops that never run.
+///
+/// This catches what the lexer cannot see, such as a macro ending with `<` followed by one
+/// starting with `>`. A loop never runs when the current cell is known to be 0: right after
+/// another loop, or before anything was written to the tape. Additions in opposite directions
+/// only cancel with wrapping cells, since saturating or failing half way through matters.
+pub fn peephole(
+    program: Vec<Spanned<Instruction>>,
+    tape: &TapeConfig,
+) -> Vec<Spanned<Instruction>> {
+    peephole_block(program, tape, true)
+}
+
+/// Runs `peephole` over a program or loop body. `fresh` tells whether the tape is still all 0.
+fn peephole_block(
+    program: Vec<Spanned<Instruction>>,
+    tape: &TapeConfig,
+    mut fresh: bool,
+) -> Vec<Spanned<Instruction>> {
+    let wrap = tape.overflow == Overflow::Wrap;
+    let mut output: Vec<Spanned<Instruction>> = Vec::with_capacity(program.len());
+    // Whether the current cell is known to be 0
+    let mut zero = fresh;
+
+    for instruction in program {
+        let span = instruction.span;
+        let node = match instruction.node {
+            // Simplified loops are just as dead
+            Instruction::Loop { .. }
+            | Instruction::Scan { .. }
+            | Instruction::MulAdd { src: 0, .. }
+            | Instruction::SetZero { offset: 0 }
+                if zero =>
+            {
+                continue
+            }
+            Instruction::Loop { body } => Instruction::Loop {
+                body: peephole_block(body, tape, false),
+            },
+            node => node,
+        };
+
+        match node {
+            Instruction::Move(_) => zero = fresh,
+            Instruction::Loop { .. } | Instruction::Scan { .. } => zero = true,
+            Instruction::Add { offset: 0, .. }
+            | Instruction::Input { offset: 0, .. }
+            | Instruction::MulAdd { dst: 0, .. }
+            | Instruction::Init { .. } => zero = false,
+            _ => {}
+        }
+        fresh &= matches!(
+            node,
+            Instruction::Move(_) | Instruction::Output { .. } | Instruction::Print { .. }
+        );
+
+        // A merged instruction takes the span of the last part, which is where a move leaves
+        // the tape or an addition overflows
+        match (output.last_mut(), &node) {
+            (
+                Some(Spanned {
+                    node:
+                        Instruction::Add {
+                            offset: last,
+                            delta: total,
+                        },
+                    span: merged,
+                }),
+                Instruction::Add { offset, delta },
+            ) if last == offset && (wrap || total.signum() == delta.signum()) => {
+                *total += delta;
+                *merged = span;
+            }
+            (
+                Some(Spanned {
+                    node: Instruction::Move(total),
+                    span: merged,
+                }),
+                Instruction::Move(distance),
+            ) => {
+                *total += distance;
+                *merged = span;
+            }
+            _ => output.push(Spanned::new(node, span)),
+        }
+        if matches!(
+            output.last().map(|i| &i.node),
+            Some(Instruction::Add { delta: 0, .. } | Instruction::Move(0))
+        ) {
+            output.pop();
+        }
+    }
+
+    output
+}
+
+/// Replaces loops that only add to cells around a fixed pointer with direct assignments.
+///
+/// `[-]` becomes a `SetZero`, and a loop such as `[->+++>+<<]` becomes a `MulAdd` for every
+/// other cell it touches followed by a `SetZero` of the current cell. Nested loops are
+/// simplified first.
+pub fn simplify_loops(
+    program: Vec<Spanned<Instruction>>,
+    tape: &TapeConfig,
+) -> Vec<Spanned<Instruction>> {
+    let mut output = Vec::with_capacity(program.len());
+
+    for instruction in program {
+        let span = instruction.span;
+        let Instruction::Loop { body } = instruction.node else {
+            output.push(instruction);
+            continue;
+        };
+
+        let body = simplify_loops(body, tape);
+        match multiplications(&body, tape) {
+            Some(factors) => {
+                output.extend(factors.into_iter().map(|(dst, factor)| {
+                    Spanned::new(
+                        Instruction::MulAdd {
+                            src: 0,
+                            dst,
+                            factor,
+                        },
+                        span,
+                    )
+                }));
+                output.push(Spanned::new(Instruction::SetZero { offset: 0 }, span));
+            }
+            None => output.push(Spanned::new(Instruction::Loop { body }, span)),
+        }
+    }
+
+    output
+}
+
+/// Replaces loops that only move the pointer, such as `[>]` or `[<<]`, with a `Scan`.
+pub fn simplify_scans(program: Vec<Spanned<Instruction>>) -> Vec<Spanned<Instruction>> {
+    program
+        .into_iter()
+        .map(|instruction| {
+            let span = instruction.span;
+            let Instruction::Loop { body } = instruction.node else {
+                return instruction;
+            };
+            let node = match body.as_slice() {
+                [Spanned {
+                    node: Instruction::Move(step),
+                    ..
+                }] if *step != 0 => Instruction::Scan { step: *step },
+                _ => Instruction::Loop {
+                    body: simplify_scans(body),
+                },
+            };
+            Spanned::new(node, span)
+        })
+        .collect()
+}
+
+/// Replaces pointer moves in straight-line code with offsets, so `>+>+<<-` becomes
+/// `ptr[1] += 1; ptr[2] += 1; ptr[0] -= 1` without moving the pointer at all.
+///
+/// The pointer only moves before loops, scans, input and output, and at the end of the
+/// program or loop body.
+pub fn fold_offsets(program: Vec<Spanned<Instruction>>) -> Vec<Spanned<Instruction>> {
+    let mut output = Vec::with_capacity(program.len());
+    // How far the pointer should have moved, and the span of the last move
+    let mut pending: Option<(isize, Span)> = None;
+
+    for instruction in program {
+        let span = instruction.span;
+        let offset = pending.map_or(0, |(distance, _)| distance);
+        let node = match instruction.node {
+            Instruction::Move(distance) => {
+                pending = Some((offset + distance, span));
+                continue;
+            }
+            Instruction::Add { offset: o, delta } => Instruction::Add {
+                offset: o + offset,
+                delta,
+            },
+            Instruction::SetZero { offset: o } => Instruction::SetZero { offset: o + offset },
+            Instruction::MulAdd { src, dst, factor } => Instruction::MulAdd {
+                src: src + offset,
+                dst: dst + offset,
+                factor,
+            },
+            Instruction::Loop { body } => {
+                flush(&mut output, pending.take());
+                Instruction::Loop {
+                    body: fold_offsets(body),
+                }
+            }
+            node => {
+                flush(&mut output, pending.take());
+                node
+            }
+        };
+        output.push(Spanned::new(node, span));
+    }
+
+    flush(&mut output, pending);
+    output
+}
+
+/// Emits the pointer move left pending by `fold_offsets`, if there is one.
+fn flush(output: &mut Vec<Spanned<Instruction>>, pending: Option<(isize, Span)>) {
+    if let Some((distance, span)) = pending.filter(|&(distance, _)| distance != 0) {
+        output.push(Spanned::new(Instruction::Move(distance), span));
+    }
+}
+
+/// Runs the start of a program at compile time and replaces it with the output it printed,
+/// the tape it left behind and a move to where it left the pointer.
+///
+/// Evaluation stops at the first input or once `budget` instructions ran, loop iterations
+/// included. Only whole top-level instructions are evaluated: the one that reads input, fails
+/// or runs out of budget is left for run time, together with everything after it. The program
+/// has to start on an all 0 tape.
+pub fn evaluate_prefix(
+    program: Vec<Spanned<Instruction>>,
+    tape: &TapeConfig,
+    budget: u64,
+) -> Vec<Spanned<Instruction>> {
+    let mut evaluator = Evaluator {
+        tape,
+        cells: vec![0; tape.tape_length],
+        pointer: 0,
+        output: Vec::new(),
+        steps: budget,
+        journal: Vec::new(),
+    };
+
+    let mut evaluated = 0;
+    for instruction in &program {
+        let (pointer, printed) = (evaluator.pointer, evaluator.output.len());
+        if evaluator.run(slice::from_ref(instruction)).is_none() {
+            evaluator.rollback(pointer, printed);
+            break;
+        }
+        evaluator.journal.clear();
+        evaluated += 1;
+    }
+    if evaluated == 0 {
+        return program;
+    }
+
+    let span = program[0].span;
+    let mut output = Vec::new();
+    if !evaluator.output.is_empty() {
+        let bytes = evaluator.output;
+        output.push(Spanned::new(Instruction::Print { bytes }, span));
+    }
+    if let Some(last) = evaluator.cells.iter().rposition(|&cell| cell != 0) {
+        evaluator.cells.truncate(last + 1);
+        let cells = evaluator.cells;
+        output.push(Spanned::new(Instruction::Init { cells }, span));
+    }
+    if evaluator.pointer != 0 {
+        output.push(Spanned::new(
+            Instruction::Move(evaluator.pointer as isize),
+            span,
+        ));
+    }
+    output.extend(program.into_iter().skip(evaluated));
+    output
+}
+
+/// Runs instructions at compile time for `evaluate_prefix`.
+struct Evaluator<'a> {
+    /// The tape semantics to honour.
+    tape: &'a TapeConfig,
+    /// The tape.
+    cells: Vec<i64>,
+    /// The index of the current cell.
+    pointer: usize,
+    /// Everything printed so far.
+    output: Vec<u8>,
+    /// How many more instructions may run.
+    steps: u64,
+    /// The cells changed by the instruction being evaluated, with their previous values.
+    journal: Vec<(usize, i64)>,
+}
+
+impl Evaluator<'_> {
+    /// Runs instructions, returning `None` on input, on an error or when the budget runs out.
+    fn run(&mut self, program: &[Spanned<Instruction>]) -> Option<()> {
+        for instruction in program {
+            self.steps = self.steps.checked_sub(1)?;
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    let cell = self.cell(*offset)?;
+                    let value = self.tape.add(self.cells[cell], *delta)?;
+                    self.set(cell, value);
+                }
+                Instruction::Move(distance) => self.pointer = self.cell(*distance)?,
+                Instruction::SetZero { offset } => {
+                    let cell = self.cell(*offset)?;
+                    self.set(cell, 0);
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    let src = self.cell(*src)?;
+                    if self.cells[src] != 0 {
+                        let dst = self.cell(*dst)?;
+                        let value =
+                            self.tape
+                                .multiply_add(self.cells[dst], self.cells[src], *factor)?;
+                        self.set(dst, value);
+                    }
+                }
+                Instruction::Scan { step } => {
+                    while self.cells[self.pointer] != 0 {
+                        self.steps = self.steps.checked_sub(1)?;
+                        self.pointer = self.cell(*step)?;
+                    }
+                }
+                Instruction::Loop { body } => {
+                    while self.cells[self.pointer] != 0 {
+                        // An empty body still costs a step, or `[]` would never stop
+                        self.steps = self.steps.checked_sub(1)?;
+                        self.run(body)?;
+                    }
+                }
+                Instruction::Output { offset, count } => {
+                    let byte = self.cells[self.cell(*offset)?] as u8;
+                    self.output.extend(iter::repeat_n(byte, *count));
+                }
+                Instruction::Input { .. } => return None,
+                Instruction::Print { bytes } => self.output.extend_from_slice(bytes),
+                Instruction::Init { cells } => {
+                    for (offset, &value) in cells.iter().enumerate() {
+                        let cell = self.cell(offset as isize)?;
+                        self.set(cell, value);
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Returns the index of the cell `offset` cells away from the pointer, if it is on the tape.
+    fn cell(&self, offset: isize) -> Option<usize> {
+        let index = self.pointer.checked_add_signed(offset)?;
+        (index < self.cells.len()).then_some(index)
+    }
+
+    /// Stores a value, remembering the previous one in the journal.
+    fn set(&mut self, index: usize, value: i64) {
+        self.journal.push((index, self.cells[index]));
+        self.cells[index] = value;
+    }
+
+    /// Undoes everything since the journal was last cleared.
+    fn rollback(&mut self, pointer: usize, printed: usize) {
+        while let Some((index, value)) = self.journal.pop() {
+            self.cells[index] = value;
+        }
+        self.pointer = pointer;
+        self.output.truncate(printed);
+    }
+}
+
+/// Works out what a loop body adds to the cells around the current one each time the current
+/// cell counts down to 0, as `(offset, factor)` pairs in the order the cells are first touched.
+///
+/// Returns `None` unless the body only adds and moves, returns to where it started, and
+/// changes the current cell by exactly one per iteration. Without wrapping cells the current
+/// cell has to count down from an unsigned value and every cell is added to at most once, so
+/// saturating or failing mid-loop behaves the same as a single addition.
+fn multiplications(body: &[Spanned<Instruction>], tape: &TapeConfig) -> Option<Vec<(isize, i64)>> {
+    let wrap = tape.overflow == Overflow::Wrap;
+    if !wrap && tape.signed {
+        return None;
+    }
+
+    let mut pointer = 0;
+    // Every cell the body adds to, with the total it adds and how many times
+    let mut deltas: Vec<(isize, i64, usize)> = Vec::new();
+    for instruction in body {
+        match instruction.node {
+            Instruction::Add { offset, delta } => {
+                let offset = pointer + offset;
+                match deltas.iter_mut().find(|(o, _, _)| *o == offset) {
+                    Some((_, total, count)) => {
+                        *total += delta;
+                        *count += 1;
+                    }
+                    None => deltas.push((offset, delta, 1)),
+                }
+            }
+            Instruction::Move(distance) => pointer += distance,
+            _ => return None,
+        }
+    }
+    if pointer != 0 || (!wrap && deltas.iter().any(|&(_, _, count)| count > 1)) {
+        return None;
+    }
+
+    let step = deltas.iter().find(|(o, _, _)| *o == 0).map(|&(_, d, _)| d);
+    // Counting up to 0 runs the loop -value times, which flips every factor
+    let sign = match step {
+        Some(-1) => 1,
+        Some(1) if wrap => -1,
+        _ => return None,
+    };
+    Some(
+        deltas
+            .into_iter()
+            .filter(|&(offset, delta, _)| offset != 0 && delta != 0)
+            .map(|(offset, delta, _)| (offset, delta * sign))
+            .collect(),
+    )
+}
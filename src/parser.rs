@@ -1,7 +1,13 @@
+use crate::error::BraincrapError;
+use crate::span::{SourceMap, Span, Spanned};
+use crate::stdlib;
 use crate::tokenizer::BraincrapToken;
-use crate::tokenizer::Lexer;
+use crate::tokenizer::{is_name_char, parameter_references, Lexer};
+use log::debug;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
 
 /// Represents a Braincrap command that the parser recognizes.
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -15,31 +21,108 @@ pub enum BraincrapCommand {
     Output(usize),
     Input(usize),
     /// Defines a macro
+    ///
+    /// `code` is empty for macros with parameters, their `tokens` are parsed again at every call
+    /// with the arguments substituted.
     DefineMacro {
-        name: char,
-        tokens: Vec<BraincrapToken>,
-        code: Vec<BraincrapCommand>,
+        name: String,
+        parameters: Vec<MacroParameter>,
+        tokens: Vec<Spanned<BraincrapToken>>,
+        code: Vec<Spanned<BraincrapCommand>>,
     },
     /// Runs a macro
     RunMacro {
-        name: char,
+        name: String,
+    },
+    /// Runs a macro with arguments
+    CallMacro {
+        name: String,
+        arguments: Vec<Spanned<String>>,
     },
     /// Imports another Braincrap script or library from a file
+    ///
+    /// `path` is the canonical path of the file, which identifies it when it is imported more
+    /// than once. With an `alias`, its macros are only visible as `alias::name`.
     Import {
         file: String,
-        tokens: Vec<BraincrapToken>,
-        code: Vec<BraincrapCommand>,
+        path: PathBuf,
+        alias: Option<String>,
+        tokens: Vec<Spanned<BraincrapToken>>,
+        code: Vec<Spanned<BraincrapCommand>>,
     },
 }
 
+/// A parameter of a macro, written as `name` or `name=default` in the definition.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct MacroParameter {
+    pub name: String,
+    /// The argument used when a call does not pass one.
+    pub default: Option<String>,
+}
+
+/// Keeps track of loaded files. Shared by a parser, every nested parser it spawns and the
+/// expander, which parses the bodies of macros with parameters again at every call.
+pub struct ImportState {
+    /// Every file loaded so far, so spans can be rendered in diagnostics.
+    sources: SourceMap,
+    /// The directory relative imports are resolved from in every file, by canonical path. The
+    /// main program is the empty path.
+    directories: HashMap<PathBuf, PathBuf>,
+    /// The files currently being parsed, outermost first, as (canonical path, path as written).
+    stack: Vec<(PathBuf, PathBuf)>,
+    /// Canonical paths of every file imported so far.
+    loaded: HashSet<PathBuf>,
+    /// Whether importing an already loaded file is skipped.
+    include_once: bool,
+    /// Directories searched, in order, for library imports.
+    include_dirs: Vec<PathBuf>,
+}
+
+impl ImportState {
+    /// Returns every file loaded so far.
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Consumes the import state, returning every file loaded so far.
+    pub fn into_sources(self) -> SourceMap {
+        self.sources
+    }
+
+    /// Returns the directory relative imports in `module` are resolved from.
+    ///
+    /// # Arguments
+    /// * `module` - The canonical path of the file, or the empty path for the main program.
+    pub(crate) fn directory(&self, module: &Path) -> PathBuf {
+        self.directories.get(module).cloned().unwrap_or_default()
+    }
+}
+
+impl Default for ImportState {
+    fn default() -> Self {
+        Self {
+            sources: SourceMap::new(),
+            directories: HashMap::new(),
+            stack: Vec::new(),
+            loaded: HashSet::new(),
+            include_once: true,
+            include_dirs: Vec::new(),
+        }
+    }
+}
+
 /// A parser for Braincrap language tokens.
 pub struct Parser<'a> {
     /// Stores the current working directory for resolving relative imports.
     pwd: PathBuf,
     /// A slice of Braincrap tokens to be parsed.
-    tokens: &'a [BraincrapToken],
+    tokens: &'a [Spanned<BraincrapToken>],
     /// Tracks the current position within the token stream.
     current_position: usize,
+    /// Loaded files, import chain and include-once bookkeeping.
+    imports: ImportState,
+    /// The parameters of every macro defined so far in this file, so calls can be checked early.
+    macros: HashMap<String, Vec<MacroParameter>>,
 }
 
 impl<'a> Parser<'a> {
@@ -48,54 +131,328 @@ impl<'a> Parser<'a> {
     /// # Arguments
     /// * `tokens` - A slice of `BraincrapToken` representing the input program.
     /// * `pwd` - The current working directory for handling file imports.
-    pub fn new(tokens: &'a [BraincrapToken], pwd: PathBuf) -> Self {
+    pub fn new(tokens: &'a [Spanned<BraincrapToken>], pwd: PathBuf) -> Self {
+        Self::with_sources(tokens, pwd, SourceMap::new())
+    }
+
+    /// Creates a new `Parser` instance that registers imported files in `sources`.
+    ///
+    /// # Arguments
+    /// * `tokens` - A slice of `BraincrapToken` representing the input program.
+    /// * `pwd` - The current working directory for handling file imports.
+    /// * `sources` - The files loaded so far, usually holding the file `tokens` came from.
+    pub fn with_sources(
+        tokens: &'a [Spanned<BraincrapToken>],
+        pwd: PathBuf,
+        sources: SourceMap,
+    ) -> Self {
+        let imports = ImportState {
+            sources,
+            directories: HashMap::from([(PathBuf::new(), pwd.clone())]),
+            ..ImportState::default()
+        };
+        Self::with_imports(tokens, pwd, imports)
+    }
+
+    /// Creates a new `Parser` instance that continues with the files loaded by another parser.
+    ///
+    /// # Arguments
+    /// * `tokens` - A slice of `BraincrapToken` representing the input program.
+    /// * `pwd` - The current working directory for handling file imports.
+    /// * `imports` - The import state returned by `into_imports`.
+    pub(crate) fn with_imports(
+        tokens: &'a [Spanned<BraincrapToken>],
+        pwd: PathBuf,
+        imports: ImportState,
+    ) -> Self {
         Parser {
             pwd,
             tokens,
             current_position: 0,
+            imports,
+            macros: HashMap::new(),
         }
     }
 
-    /// Parses a macro definition.
+    /// Records the file the tokens were read from, so importing it again is detected as a cycle.
+    pub fn set_file(&mut self, path: &Path) {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.imports.loaded.insert(canonical.clone());
+        self.imports.stack.push((canonical, path.to_path_buf()));
+    }
+
+    /// Sets whether a file that was already imported is skipped when imported again.
+    /// This is enabled by default.
+    pub fn set_include_once(&mut self, include_once: bool) {
+        self.imports.include_once = include_once;
+    }
+
+    /// Sets the directories searched, in order, for library imports.
+    ///
+    /// Imports starting with `./` or `../` (such as `$./utils.bf`) are relative imports and are
+    /// only looked up next to the importing file. Any other relative path (such as `$std.bf`) is a
+    /// library import: it is looked up in these directories first, then next to the importing file.
+    pub fn set_include_dirs(&mut self, include_dirs: Vec<PathBuf>) {
+        self.imports.include_dirs = include_dirs;
+    }
+
+    /// Returns every file loaded so far.
+    pub fn sources(&self) -> &SourceMap {
+        &self.imports.sources
+    }
+
+    /// Consumes the parser, returning every file it loaded.
+    pub fn into_sources(self) -> SourceMap {
+        self.imports.sources
+    }
+
+    /// Consumes the parser, returning the files it loaded together with the include directories
+    /// and include-once bookkeeping, for `Expander::with_imports`.
+    pub fn into_imports(self) -> ImportState {
+        self.imports
+    }
+
+    /// Runs a nested parser over `tokens` that shares this parser's import state.
+    fn parse_nested(
+        &mut self,
+        tokens: &[Spanned<BraincrapToken>],
+        pwd: PathBuf,
+    ) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+        let mut nested_parser = Parser {
+            pwd,
+            tokens,
+            current_position: 0,
+            imports: mem::take(&mut self.imports),
+            macros: mem::take(&mut self.macros),
+        };
+        let code = nested_parser.parse();
+        self.imports = nested_parser.imports;
+        self.macros = nested_parser.macros;
+        code
+    }
+
+    /// Parses a macro definition. Every loop opened in the body must be closed in it.
     ///
     /// # Arguments
     /// * `name` - The name of the macro.
-    /// * `tokens` - The list of tokens forming the macro body.
-    fn parse_macro(&mut self, name: char, tokens: Vec<BraincrapToken>) -> BraincrapCommand {
-        let mut nested_parser = Parser::new(tokens.as_slice(), self.pwd.clone());
-        let code = nested_parser.parse();
+    /// * `parameters` - The parameter list, if the definition has one.
+    /// * `body` - The macro body.
+    /// * `span` - Where the macro body is written.
+    fn parse_macro(
+        &mut self,
+        name: String,
+        parameters: Option<Spanned<Vec<Spanned<String>>>>,
+        body: String,
+        span: Span,
+    ) -> Result<BraincrapCommand, BraincrapError> {
+        let parameters = match parameters {
+            Some(Spanned { node, .. }) => parse_parameters(&name, node)?,
+            None => Vec::new(),
+        };
+        let mut lexer = Lexer::with_origin(body, span);
+        let tokens = lexer.tokenize()?;
+        for token in &tokens {
+            let references = match &token.node {
+                BraincrapToken::Parameter(parameter) => vec![(parameter.clone(), token.span)],
+                BraincrapToken::Arguments(arguments) => arguments
+                    .iter()
+                    .flat_map(|argument| {
+                        parameter_references(&argument.node)
+                            .into_iter()
+                            .map(|parameter| (parameter, argument.span))
+                    })
+                    .collect(),
+                _ => continue,
+            };
+            for (parameter, span) in references {
+                if !parameters.iter().any(|p| p.name == parameter) {
+                    return Err(BraincrapError::InvalidMacroArgument {
+                        name,
+                        message: format!("unknown parameter %{parameter}"),
+                        span,
+                    });
+                }
+            }
+        }
+        self.macros.insert(name.clone(), parameters.clone());
+
+        if !parameters.is_empty() {
+            return Ok(BraincrapCommand::DefineMacro {
+                name,
+                parameters,
+                tokens,
+                code: Vec::new(),
+            });
+        }
 
-        BraincrapCommand::DefineMacro { name, tokens, code }
+        let code = self.parse_nested(&tokens, self.pwd.clone())?;
+        match_loops(&code)?;
+
+        Ok(BraincrapCommand::DefineMacro {
+            name,
+            parameters,
+            tokens,
+            code,
+        })
+    }
+
+    /// Parses a macro call.
+    ///
+    /// Calls to macros that are already defined have their arguments checked here. Calls to
+    /// macros defined later are checked when they are expanded.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the macro.
+    /// * `arguments` - The argument list, if the call has one.
+    /// * `span` - Where the call is written.
+    fn parse_call(
+        &mut self,
+        name: String,
+        arguments: Option<Vec<Spanned<String>>>,
+        span: Span,
+    ) -> Result<BraincrapCommand, BraincrapError> {
+        if let Some(parameters) = self.macros.get(&name) {
+            bind_arguments(
+                &name,
+                parameters,
+                arguments.as_deref().unwrap_or_default(),
+                span,
+            )?;
+        }
+
+        Ok(match arguments {
+            Some(arguments) => BraincrapCommand::CallMacro { name, arguments },
+            None => BraincrapCommand::RunMacro { name },
+        })
+    }
+
+    /// Finds the file an import refers to.
+    ///
+    /// # Arguments
+    /// * `filename` - The path as written after `$`.
+    fn resolve_import(&self, filename: &str) -> PathBuf {
+        let path = Path::new(filename);
+        let is_library = match path.components().next() {
+            Some(Component::CurDir | Component::ParentDir) => false,
+            _ => path.is_relative(),
+        };
+
+        if is_library {
+            for dir in &self.imports.include_dirs {
+                let candidate = dir.join(path);
+                debug!("Looking for {filename} in {}", dir.display());
+                if candidate.is_file() {
+                    return candidate;
+                }
+            }
+        }
+        self.pwd.join(path)
+    }
+
+    /// Reads the file an import refers to, returning its path, canonical path and contents.
+    ///
+    /// Imports starting with `stdlib::PREFIX` are served from the embedded standard library.
+    ///
+    /// # Arguments
+    /// * `filename` - The path as written after `$`.
+    /// * `span` - Where the import statement is written.
+    fn load_import(
+        &self,
+        filename: &str,
+        span: Span,
+    ) -> Result<(PathBuf, PathBuf, String), BraincrapError> {
+        if let Some(name) = filename.strip_prefix(stdlib::PREFIX) {
+            let source = stdlib::get(name).ok_or_else(|| BraincrapError::ImportNotFound {
+                path: PathBuf::from(filename),
+                span: Some(span),
+            })?;
+            return Ok((
+                PathBuf::from(filename),
+                PathBuf::from(filename),
+                source.to_string(),
+            ));
+        }
+
+        let filepath = self.resolve_import(filename);
+        let file_content =
+            fs::read_to_string(&filepath).map_err(|_| BraincrapError::ImportNotFound {
+                path: filepath.clone(),
+                span: Some(span),
+            })?;
+        let canonical = filepath.canonicalize().unwrap_or_else(|_| filepath.clone());
+        Ok((filepath, canonical, file_content))
     }
 
     /// Parses an import statement and loads another Braincrap script.
     ///
     /// # Arguments
     /// * `filename` - The path to the file to be imported.
-    fn parse_import(&mut self, filename: String) -> BraincrapCommand {
-        let filepath = self.pwd.join(&filename);
-        let file_content = fs::read_to_string(&filepath).unwrap_or_else(|_| {
-            eprintln!("Failed to read file: {}", filepath.display());
-            String::new()
-        });
+    /// * `alias` - The namespace the file's macros are placed in, if any.
+    /// * `span` - Where the import statement is written.
+    fn parse_import(
+        &mut self,
+        filename: String,
+        alias: Option<String>,
+        span: Span,
+    ) -> Result<BraincrapCommand, BraincrapError> {
+        let (filepath, canonical, file_content) = self.load_import(&filename, span)?;
 
-        let mut lexer = Lexer::new(file_content);
-        let tokens = lexer.tokenize();
-        let mut nested_parser = Parser::new(
-            &tokens,
-            filepath.parent().unwrap_or(&self.pwd).to_path_buf(),
-        );
-        let code = nested_parser.parse();
+        if let Some(start) = self.imports.stack.iter().position(|(c, _)| *c == canonical) {
+            let mut chain: Vec<PathBuf> = self.imports.stack[start..]
+                .iter()
+                .map(|(_, path)| path.clone())
+                .collect();
+            chain.push(filepath);
+            return Err(BraincrapError::ImportCycle { chain, span });
+        }
+
+        if !self.imports.loaded.insert(canonical.clone()) && self.imports.include_once {
+            debug!("Skipping {}, it is already imported", filepath.display());
+            return Ok(BraincrapCommand::Import {
+                file: filename,
+                path: canonical,
+                alias,
+                tokens: Vec::new(),
+                code: Vec::new(),
+            });
+        }
+
+        let file = self
+            .imports
+            .sources
+            .add(filepath.clone(), file_content.clone());
+        let mut lexer = Lexer::with_file(file_content, file);
+        let tokens = lexer.tokenize()?;
+
+        // Embedded libraries have no directory, nested imports resolve from the importer
+        let pwd = match filepath.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => self.pwd.clone(),
+        };
+        self.imports
+            .directories
+            .insert(canonical.clone(), pwd.clone());
+        // Every file starts with no macros of its own
+        let macros = mem::take(&mut self.macros);
+        self.imports
+            .stack
+            .push((canonical.clone(), filepath.clone()));
+        let code = self.parse_nested(&tokens, pwd);
+        self.imports.stack.pop();
+        self.macros = macros;
+        let code = code?;
 
-        BraincrapCommand::Import {
+        Ok(BraincrapCommand::Import {
             file: filename,
+            path: canonical,
+            alias,
             tokens,
             code,
-        }
+        })
     }
 
     /// Retrieves the next token from the stream, advancing the position.
-    fn next_token(&mut self) -> Option<BraincrapToken> {
+    fn next_token(&mut self) -> Option<Spanned<BraincrapToken>> {
         if self.current_position < self.tokens.len() {
             self.current_position += 1;
             Some(self.tokens[self.current_position - 1].clone())
@@ -104,59 +461,257 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Consumes the next token if it is an argument list.
+    fn next_arguments(&mut self) -> Option<Spanned<Vec<Spanned<String>>>> {
+        let Some(BraincrapToken::Arguments(_)) = self.peek_previous() else {
+            return None;
+        };
+        let token = self.next_token()?;
+        match token.node {
+            BraincrapToken::Arguments(arguments) => Some(Spanned::new(arguments, token.span)),
+            _ => None,
+        }
+    }
+
     /// Retrieves the previous token without advancing the position.
     fn peek_previous(&mut self) -> Option<BraincrapToken> {
         if self.current_position < self.tokens.len() {
-            Some(self.tokens[self.current_position].clone())
+            Some(self.tokens[self.current_position].node.clone())
         } else {
             None
         }
     }
 
     /// Parses the token stream and produces a list of `BraincrapCommand`s.
-    pub fn parse(&mut self) -> Vec<BraincrapCommand> {
+    ///
+    /// # Errors
+    /// Returns an error if a macro body is illegal or has unbalanced loops, or if an import cannot
+    /// be loaded.
+    pub fn parse(&mut self) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
         let mut commands = Vec::new();
 
-        while let Some(token) = self.next_token() {
-            match token {
-                BraincrapToken::Plus(count) => commands.push(BraincrapCommand::Addition(count)),
-                BraincrapToken::Minus(count) => {
-                    commands.push(BraincrapCommand::Substraction(count))
-                }
-                BraincrapToken::Left(count) => commands.push(BraincrapCommand::MoveLeft(count)),
-                BraincrapToken::Right(count) => commands.push(BraincrapCommand::MoveRight(count)),
-                BraincrapToken::LeftBracket => commands.push(BraincrapCommand::OpenLoop),
-                BraincrapToken::RightBracket => commands.push(BraincrapCommand::CloseLoop),
-                BraincrapToken::Dot(count) => commands.push(BraincrapCommand::Output(count)),
-                BraincrapToken::Comma(count) => commands.push(BraincrapCommand::Input(count)),
+        while let Some(Spanned {
+            node: token,
+            mut span,
+        }) = self.next_token()
+        {
+            let command = match token {
+                BraincrapToken::Plus(count) => BraincrapCommand::Addition(count),
+                BraincrapToken::Minus(count) => BraincrapCommand::Substraction(count),
+                BraincrapToken::Left(count) => BraincrapCommand::MoveLeft(count),
+                BraincrapToken::Right(count) => BraincrapCommand::MoveRight(count),
+                BraincrapToken::LeftBracket => BraincrapCommand::OpenLoop,
+                BraincrapToken::RightBracket => BraincrapCommand::CloseLoop,
+                BraincrapToken::Dot(count) => BraincrapCommand::Output(count),
+                BraincrapToken::Comma(count) => BraincrapCommand::Input(count),
 
                 BraincrapToken::Hash => {
-                    if let Some(BraincrapToken::Char(name)) = self.next_token() {
-                        if let Some(BraincrapToken::String(code_string)) = self.next_token() {
-                            let mut lexer = Lexer::new(code_string);
-                            let tokens = lexer.tokenize();
-                            commands.push(self.parse_macro(name, tokens));
-                        }
-                    }
+                    let Some(Spanned {
+                        node: BraincrapToken::Name(name),
+                        ..
+                    }) = self.next_token()
+                    else {
+                        continue;
+                    };
+                    let parameters = self.next_arguments();
+                    let Some(Spanned {
+                        node: BraincrapToken::String(code_string),
+                        span: code_span,
+                    }) = self.next_token()
+                    else {
+                        continue;
+                    };
+                    span = span.to(code_span);
+                    self.parse_macro(name, parameters, code_string, code_span)?
                 }
 
                 BraincrapToken::Dollar => {
-                    if let Some(BraincrapToken::String(filename)) = self.next_token() {
-                        commands.push(self.parse_import(filename));
-                    }
+                    let Some(Spanned {
+                        node: BraincrapToken::String(filename),
+                        span: filename_span,
+                    }) = self.next_token()
+                    else {
+                        continue;
+                    };
+                    span = span.to(filename_span);
+                    let alias = match self.peek_previous() {
+                        Some(BraincrapToken::Alias(alias)) => {
+                            let alias_span = self.next_token().map_or(span, |t| t.span);
+                            span = span.to(alias_span);
+                            Some(alias)
+                        }
+                        _ => None,
+                    };
+                    self.parse_import(filename, alias, span)?
                 }
 
-                BraincrapToken::Char(m) => {
-                    if let Some(BraincrapToken::Hash) = self.peek_previous() {
-                        // Skip macro definition name token handling here
-                    } else {
-                        commands.push(BraincrapCommand::RunMacro { name: m });
+                BraincrapToken::Char(m) => self.parse_call(m.to_string(), None, span)?,
+                BraincrapToken::Name(name) => {
+                    let arguments = self.next_arguments();
+                    if let Some(arguments) = &arguments {
+                        span = span.to(arguments.span);
                     }
+                    self.parse_call(name, arguments.map(|a| a.node), span)?
+                }
+                BraincrapToken::String(_)
+                | BraincrapToken::Alias(_)
+                | BraincrapToken::Arguments(_)
+                | BraincrapToken::Parameter(_) => continue,
+            };
+            commands.push(Spanned::new(command, span));
+        }
+
+        Ok(commands)
+    }
+}
+
+/// Parses the parameter list of a macro definition.
+///
+/// # Errors
+/// Returns `BraincrapError::InvalidMacroArgument` if a parameter name is not a valid name or is
+/// used twice.
+fn parse_parameters(
+    name: &str,
+    parameters: Vec<Spanned<String>>,
+) -> Result<Vec<MacroParameter>, BraincrapError> {
+    let mut parsed: Vec<MacroParameter> = Vec::new();
+
+    for Spanned {
+        node: parameter,
+        span,
+    } in parameters
+    {
+        let (parameter, default) = match parameter.split_once('=') {
+            Some((parameter, default)) => (parameter.trim(), Some(default.trim().to_string())),
+            None => (parameter.trim(), None),
+        };
+        let message = if parameter.is_empty() || !parameter.chars().all(is_name_char) {
+            format!("invalid parameter name {parameter:?}")
+        } else if parsed.iter().any(|p| p.name == parameter) {
+            format!("parameter {parameter} is declared twice")
+        } else {
+            parsed.push(MacroParameter {
+                name: parameter.to_string(),
+                default,
+            });
+            continue;
+        };
+        return Err(BraincrapError::InvalidMacroArgument {
+            name: name.to_string(),
+            message,
+            span,
+        });
+    }
+
+    Ok(parsed)
+}
+
+/// Pairs every `OpenLoop` with its `CloseLoop`, returning the jump target of each loop command.
+///
+/// # Errors
+/// Returns `BraincrapError::UnbalancedBrackets` pointing at the first bracket without a match.
+pub fn match_loops(program: &[Spanned<BraincrapCommand>]) -> Result<Vec<usize>, BraincrapError> {
+    let mut jumps = vec![0; program.len()];
+    let mut stack: Vec<(usize, Span)> = Vec::new();
+
+    for (index, command) in program.iter().enumerate() {
+        match command.node {
+            BraincrapCommand::OpenLoop => stack.push((index, command.span)),
+            BraincrapCommand::CloseLoop => {
+                let (open, _) = stack.pop().ok_or(BraincrapError::UnbalancedBrackets {
+                    bracket: ']',
+                    span: command.span,
+                })?;
+                jumps[open] = index;
+                jumps[index] = open;
+            }
+            _ => {}
+        }
+    }
+
+    match stack.first() {
+        None => Ok(jumps),
+        Some(&(_, span)) => Err(BraincrapError::UnbalancedBrackets { bracket: '[', span }),
+    }
+}
+
+/// Matches the arguments of a call to the parameters of a macro.
+///
+/// Positional arguments come first, followed by keyword arguments such as `tmp=3`. Parameters
+/// that are not given an argument take their default value, which points at the call.
+///
+/// # Errors
+/// Returns `BraincrapError::WrongArgumentCount` if too many or too few arguments are given, or
+/// `BraincrapError::InvalidMacroArgument` if a keyword argument is unknown or repeated.
+pub(crate) fn bind_arguments(
+    name: &str,
+    parameters: &[MacroParameter],
+    arguments: &[Spanned<String>],
+    span: Span,
+) -> Result<HashMap<String, Spanned<String>>, BraincrapError> {
+    let invalid = |message: String| BraincrapError::InvalidMacroArgument {
+        name: name.to_string(),
+        message,
+        span,
+    };
+    let min = parameters.iter().filter(|p| p.default.is_none()).count();
+    let wrong_count = || BraincrapError::WrongArgumentCount {
+        name: name.to_string(),
+        min,
+        max: parameters.len(),
+        found: arguments.len(),
+        span,
+    };
+
+    let mut values = HashMap::new();
+    let mut positional = 0;
+    let mut keywords = false;
+    for argument in arguments {
+        let keyword = argument
+            .node
+            .split_once('=')
+            .filter(|(key, _)| !key.trim().is_empty() && key.trim().chars().all(is_name_char));
+        match keyword {
+            Some((key, value)) => {
+                let key = key.trim();
+                if !parameters.iter().any(|p| p.name == key) {
+                    return Err(invalid(format!("unknown parameter {key}")));
                 }
-                BraincrapToken::String(_) => {}
+                let value_start = argument.node.len() - value.trim_start().len();
+                let value = Spanned::new(
+                    value.trim().to_string(),
+                    argument.span.skip(&argument.node[..value_start]),
+                );
+                if values.insert(key.to_string(), value).is_some() {
+                    return Err(invalid(format!("{key} is given twice")));
+                }
+                keywords = true;
+            }
+            None if keywords => {
+                return Err(invalid(
+                    "positional argument follows keyword argument".to_string(),
+                ));
+            }
+            None => {
+                let parameter = parameters.get(positional).ok_or_else(wrong_count)?;
+                values.insert(parameter.name.clone(), argument.clone());
+                positional += 1;
             }
         }
+    }
 
-        commands
+    for parameter in parameters {
+        if values.contains_key(&parameter.name) {
+            continue;
+        }
+        match &parameter.default {
+            Some(default) => {
+                values.insert(parameter.name.clone(), Spanned::new(default.clone(), span));
+            }
+            None if arguments.len() < min => return Err(wrong_count()),
+            None => return Err(invalid(format!("missing argument {}", parameter.name))),
+        }
     }
+
+    Ok(values)
 }
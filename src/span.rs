@@ -0,0 +1,162 @@
+use crate::error::{BraincrapError, BraincrapWarning};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+/// Identifies a source file registered in a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct FileId(pub usize);
+
+/// A location in a source file.
+///
+/// `start` and `end` are byte offsets, `line` and `column` are 1-based and point at `start`.
+/// A span with `line == 0` does not point anywhere, which is what hand-built tokens get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns a span covering both `self` and `other`, which must be in the same file.
+    pub fn to(self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(self);
+        }
+        Span {
+            end: self.end.max(other.end),
+            ..self
+        }
+    }
+
+    /// Returns the span starting right after `text`, which is written at the start of `self`.
+    pub(crate) fn skip(self, text: &str) -> Span {
+        let mut span = self;
+        for c in text.chars() {
+            span.start += c.len_utf8();
+            if c == '\n' {
+                span.line += 1;
+                span.column = 1;
+            } else {
+                span.column += 1;
+            }
+        }
+        span.end = span.end.max(span.start);
+        span
+    }
+}
+
+/// A value together with the place in the source it came from.
+///
+/// Spans are metadata: two `Spanned` values compare equal when their nodes do, no matter where
+/// they were written.
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    /// Creates a new `Spanned` value.
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    /// Wraps a value with an empty span, useful for building programs by hand.
+    fn from(node: T) -> Self {
+        Self::new(node, Span::default())
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.node == *other
+    }
+}
+
+impl<T: PartialOrd> PartialOrd for Spanned<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.node.partial_cmp(&other.node)
+    }
+}
+
+/// A source file loaded while parsing.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// The path the file was loaded from.
+    pub path: PathBuf,
+    /// The contents of the file.
+    pub source: String,
+}
+
+/// Keeps every loaded source file so spans can be turned back into readable locations.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// Creates an empty `SourceMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a source file and returns its id.
+    pub fn add(&mut self, path: PathBuf, source: String) -> FileId {
+        self.files.push(SourceFile { path, source });
+        FileId(self.files.len() - 1)
+    }
+
+    /// Returns a registered source file.
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0)
+    }
+
+    /// Formats an error as `file:line:column: message`, followed by the offending line and a
+    /// caret under the column. Errors without a known location are formatted as-is.
+    pub fn render(&self, error: &BraincrapError) -> String {
+        self.render_at(error.span(), &error.to_string())
+    }
+
+    /// Formats a warning like `render`, with `warning: ` before the message.
+    pub fn render_warning(&self, warning: &BraincrapWarning) -> String {
+        self.render_at(Some(warning.span()), &format!("warning: {warning}"))
+    }
+
+    /// Formats `message` with the location of `span`, if it is known.
+    fn render_at(&self, span: Option<Span>, message: &str) -> String {
+        let Some(span) = span.filter(|s| s.line > 0) else {
+            return message.to_string();
+        };
+        let Some(file) = self.get(span.file) else {
+            return message.to_string();
+        };
+
+        let mut rendered = format!(
+            "{}:{}:{}: {message}",
+            file.path.display(),
+            span.line,
+            span.column
+        );
+        if let Some(line) = file.source.lines().nth(span.line - 1) {
+            // Keep tabs so the caret lines up with what the terminal shows
+            let padding: String = line
+                .chars()
+                .take(span.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            rendered.push_str(&format!("\n{line}\n{padding}^"));
+        }
+        rendered
+    }
+}
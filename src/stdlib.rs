@@ -0,0 +1,21 @@
+/// Version of the embedded standard library, which always matches the crate version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Marks an import of an embedded library, as in `$@std`.
+pub const PREFIX: char = '@';
+
+/// Libraries compiled into the crate, by name.
+const LIBRARIES: &[(&str, &str)] = &[("std", include_str!("stdlib/std.bf"))];
+
+/// Returns the source of the embedded library called `name`.
+pub fn get(name: &str) -> Option<&'static str> {
+    LIBRARIES
+        .iter()
+        .find(|(library, _)| *library == name)
+        .map(|(_, source)| *source)
+}
+
+/// Returns the names of every embedded library.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    LIBRARIES.iter().map(|(name, _)| *name)
+}
@@ -0,0 +1,70 @@
+; Braincrap standard library
+; Embedded in braincrap-rs and imported with $@std
+;
+; Routines are called with @ followed by their name, such as @print_num
+; Longer routines are written as blocks between { and }
+; Every routine documents its cell layout as two lines: the cells before and after it runs
+; The cell marked with > is where the pointer is
+; Cells shown as 0 must be zero before the routine runs and are zero again afterwards
+
+; ZERO
+; >n
+; >0
+#zero [-]
+
+; ADD
+; >x   y
+; >x+y 0
+#add >[<+>-]<
+
+; SUBTRACT (wraps when y > x)
+; >x   y
+; >x-y 0
+#sub >[<->-]<
+
+; MULTIPLY
+; >x y 0    0
+;  0 y >x*y 0
+#mul [>[->+>+<<]>>[-<<+>>]<<<-]>>
+
+; DIVMOD
+; >n d     0   0   0 0
+; >0 d-n%d n%d n/d 0 0
+#divmod [->-[>+>>]>[+[-<+>]>+>>]<<<<<]
+
+; DUPLICATE
+; >n 0 0
+; >n n 0
+#dup [>+>+<<-]>>[<<+>>-]<<
+
+; SHIFT
+; >n 0
+; >0 n
+#shift [>+<-]
+
+; SWAP
+; >x y 0
+; >y x 0
+#swap [>>+<<-]>[<+>-]>[<+>-]<<
+
+; PRINT NUMBER
+; Prints n in decimal, n is not modified
+; >n 0 0 0 0 0 0 0
+; >n 0 0 0 0 0 0 0
+#print_num {
+    ; Divide n by 10 into the cells after it, keeping n
+    >>++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]
+
+    ; Divide the quotient by 10 again
+    >>[-]>>>++++++++++<[->-[>+>>]>[+[- <+>]>+>>]<<<<<]
+
+    ; Print the hundreds digit if it is not zero
+    >[-]>>[>++++++[-<++++++++>]<.<<+>+>[-]]
+
+    ; Print the tens digit if it or the hundreds digit is not zero
+    <[<[->-<]++++++[->++++++++ <]>.[-]]
+
+    ; Print the ones digit and move the value back into n
+    <<++++++[-<++++++++>]<.[-]
+    <<[-<+>]<
+}
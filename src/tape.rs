@@ -0,0 +1,196 @@
+use crate::error::BraincrapError;
+use std::fmt;
+use std::str::FromStr;
+
+/// What happens when a cell is pushed past its minimum or maximum value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Wrap around modulo the cell size.
+    #[default]
+    Wrap,
+    /// Clamp to the minimum or maximum value.
+    Saturate,
+    /// Abort the program.
+    Error,
+}
+
+/// What an `Input` command stores when the input is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofBehavior {
+    /// Store 0.
+    Zero,
+    /// Store -1, which is the maximum value for unsigned cells (what `getchar()` does).
+    #[default]
+    MinusOne,
+    /// Leave the cell untouched.
+    Unchanged,
+}
+
+/// Describes the memory tape a program runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapeConfig {
+    /// Width of a cell in bits: 8, 16 or 32.
+    pub cell_bits: u32,
+    /// Whether cells hold signed values.
+    pub signed: bool,
+    /// What happens on overflow and underflow.
+    pub overflow: Overflow,
+    /// Number of cells on the tape.
+    pub tape_length: usize,
+    /// What `Input` stores on EOF.
+    pub eof: EofBehavior,
+}
+
+impl Default for TapeConfig {
+    fn default() -> Self {
+        Self {
+            cell_bits: 8,
+            signed: false,
+            overflow: Overflow::Wrap,
+            tape_length: 30000,
+            eof: EofBehavior::MinusOne,
+        }
+    }
+}
+
+impl TapeConfig {
+    /// Checks that the cell width is supported and the tape is not empty.
+    ///
+    /// # Errors
+    /// Returns `BraincrapError::InvalidTapeConfig` describing the first problem found.
+    pub fn validate(&self) -> Result<(), BraincrapError> {
+        if !matches!(self.cell_bits, 8 | 16 | 32) {
+            return Err(BraincrapError::InvalidTapeConfig {
+                message: format!(
+                    "Unsupported cell width: {} (expected 8, 16 or 32)",
+                    self.cell_bits
+                ),
+            });
+        }
+        if self.tape_length == 0 {
+            return Err(BraincrapError::InvalidTapeConfig {
+                message: "Tape length must be at least 1".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The smallest value a cell can hold.
+    pub fn min(&self) -> i64 {
+        if self.signed {
+            -(1 << (self.cell_bits - 1))
+        } else {
+            0
+        }
+    }
+
+    /// The largest value a cell can hold.
+    pub fn max(&self) -> i64 {
+        if self.signed {
+            (1 << (self.cell_bits - 1)) - 1
+        } else {
+            (1 << self.cell_bits) - 1
+        }
+    }
+
+    /// Wraps any value into the range of a cell.
+    pub fn wrap(&self, value: i128) -> i64 {
+        let size = 1i128 << self.cell_bits;
+        let wrapped = (value - i128::from(self.min())).rem_euclid(size) + i128::from(self.min());
+        wrapped as i64
+    }
+
+    /// Adds `delta` to a cell value, returning `None` if it overflows with `Overflow::Error`.
+    pub fn add(&self, value: i64, delta: i64) -> Option<i64> {
+        let result = i128::from(value) + i128::from(delta);
+        match self.overflow {
+            Overflow::Wrap => Some(self.wrap(result)),
+            Overflow::Saturate => {
+                Some(result.clamp(i128::from(self.min()), i128::from(self.max())) as i64)
+            }
+            Overflow::Error => {
+                if result < i128::from(self.min()) || result > i128::from(self.max()) {
+                    None
+                } else {
+                    Some(result as i64)
+                }
+            }
+        }
+    }
+
+    /// Adds `source * factor` to a cell value, returning `None` if it overflows with
+    /// `Overflow::Error`.
+    pub fn multiply_add(&self, value: i64, source: i64, factor: i64) -> Option<i64> {
+        let product = i128::from(source) * i128::from(factor);
+        // Anything outside of i64 is outside of every cell too
+        let delta = match self.overflow {
+            Overflow::Wrap => self.wrap(product),
+            Overflow::Saturate | Overflow::Error => {
+                product.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+            }
+        };
+        self.add(value, delta)
+    }
+
+    /// The C type used for a single cell, from `<stdint.h>`.
+    pub fn c_type(&self) -> String {
+        let sign = if self.signed { "" } else { "u" };
+        format!("{sign}int{}_t", self.cell_bits)
+    }
+
+    /// The Rust type used for a single cell.
+    pub fn rust_type(&self) -> String {
+        let sign = if self.signed { 'i' } else { 'u' };
+        format!("{sign}{}", self.cell_bits)
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(Overflow::Wrap),
+            "saturate" => Ok(Overflow::Saturate),
+            "error" => Ok(Overflow::Error),
+            _ => Err(format!(
+                "Unknown overflow behavior: {s} (expected wrap, saturate or error)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overflow::Wrap => write!(f, "wrap"),
+            Overflow::Saturate => write!(f, "saturate"),
+            Overflow::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl FromStr for EofBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" | "zero" => Ok(EofBehavior::Zero),
+            "-1" | "minus-one" => Ok(EofBehavior::MinusOne),
+            "unchanged" => Ok(EofBehavior::Unchanged),
+            _ => Err(format!(
+                "Unknown EOF behavior: {s} (expected 0, -1 or unchanged)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EofBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EofBehavior::Zero => write!(f, "0"),
+            EofBehavior::MinusOne => write!(f, "-1"),
+            EofBehavior::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
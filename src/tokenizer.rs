@@ -1,9 +1,11 @@
 /// Defines characters that cannot be used as macro names.
 const ILLEGAL_MACROS: &[char] = &[
-    '+', '-', '>', '<', '.', ',', '[', ']', ' ', '\t', '\n', '\r', '\x0C', '\x1B',
+    '+', '-', '>', '<', '.', ',', '[', ']', '@', '%', ' ', '\t', '\n', '\r', '\x0C', '\x1B',
 ];
 
-use log::error;
+use crate::error::BraincrapError;
+use crate::span::{FileId, Span, Spanned};
+use std::mem;
 
 /// Represents different token types recognized by the lexer.
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -20,16 +22,49 @@ pub enum BraincrapToken {
     Dollar,
     /// Represents a string, used for filenames or macro code.
     String(String),
-    /// Represents a character, used for macro names.
+    /// Represents a character, used for single character macro calls such as `A`.
     Char(char),
+    /// Represents a macro name, used for macro definitions and `@name` macro calls.
+    Name(String),
+    /// Represents the namespace an import is placed in, written as `$file as name`.
+    Alias(String),
+    /// Represents a `%name` reference to a macro parameter.
+    Parameter(String),
+    /// Represents a parenthesised list following a macro name: the parameters of a definition
+    /// or the arguments of a call, each with the span of its own text.
+    Arguments(Vec<Spanned<String>>),
+}
+
+/// Returns whether `c` can be part of a multi-character macro name.
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the names of the `%name` parameter references in the text of a call's argument.
+pub(crate) fn parameter_references(argument: &str) -> Vec<String> {
+    let mut references = Vec::new();
+    let mut chars = argument.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' || !chars.peek().is_some_and(|&c| is_name_char(c)) {
+            continue;
+        }
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|&c| is_name_char(c)) {
+            name.push(c);
+        }
+        references.push(name);
+    }
+    references
 }
 
 /// A lexer that converts Braincrap source code into tokens.
 pub struct Lexer {
-    /// The current position within the input string.
-    index: u64,
-    /// The Braincrap source code as a string.
-    input: String,
+    /// The current position within the input, in characters.
+    index: usize,
+    /// The Braincrap source code, split into characters.
+    input: Vec<char>,
+    /// The location of the current character.
+    position: Span,
 }
 
 impl Lexer {
@@ -38,141 +73,488 @@ impl Lexer {
     /// # Arguments
     /// * `input` - The Braincrap source code to be tokenized.
     pub fn new(input: String) -> Lexer {
-        Self { index: 0, input }
+        Self::with_file(input, FileId::default())
+    }
+
+    /// Creates a new `Lexer` instance whose spans point into `file`.
+    ///
+    /// # Arguments
+    /// * `input` - The Braincrap source code to be tokenized.
+    /// * `file` - The id of the file `input` was read from.
+    pub fn with_file(input: String, file: FileId) -> Lexer {
+        Self::with_origin(
+            input,
+            Span {
+                file,
+                start: 0,
+                end: 0,
+                line: 1,
+                column: 1,
+            },
+        )
+    }
+
+    /// Creates a new `Lexer` instance for code that starts at `origin`, such as a macro body.
+    pub(crate) fn with_origin(input: String, origin: Span) -> Lexer {
+        Self {
+            index: 0,
+            input: input.chars().collect(),
+            position: Span {
+                end: origin.start,
+                ..origin
+            },
+        }
     }
 
     /// Converts the input source code into a vector of `BraincrapToken`s.
-    pub fn tokenize(&mut self) -> Vec<BraincrapToken> {
+    ///
+    /// Macro names are either a run of letters, digits and underscores (`#print_num`) or a
+    /// single other character (`#*`). A single character macro is called by writing it, any
+    /// macro can be called with `@` followed by its name (`@print_num`).
+    ///
+    /// A macro body runs until the end of the line, or is written as a block between `{` and
+    /// `}` that can span several lines and contain comments and blank lines.
+    ///
+    /// A macro name may be directly followed by a parenthesised, comma separated list, such as
+    /// `#move(n)` or `@copy(0, 2, tmp=3)`. Arguments wrapped in braces (`{@add>}`) can contain
+    /// commas and have the braces removed. Macro bodies refer to their parameters as `%n`.
+    ///
+    /// An import can be placed in a namespace with `$std.bf as s`, its macros are then called as
+    /// `s::add` or `@s::add`.
+    ///
+    /// # Errors
+    /// Returns `BraincrapError::IllegalMacroName` if a macro is defined or called with a reserved
+    /// name, or `BraincrapError::UnbalancedBrackets` if an argument list or a macro block is never
+    /// closed.
+    pub fn tokenize(&mut self) -> Result<Vec<Spanned<BraincrapToken>>, BraincrapError> {
         let mut tokens = Vec::new();
 
-        while self.index < self.input.len() as u64 {
+        while self.index < self.input.len() {
             let current_char = self.current_char();
+            let start = self.position;
             match current_char {
                 '$' => {
-                    self.index += 1;
+                    self.advance();
+                    tokens.push(Spanned::new(BraincrapToken::Dollar, self.span_from(start)));
+
+                    let filename_start = self.position;
                     let mut filename = String::new();
-                    while self.index < self.input.len() as u64 {
+                    while self.index < self.input.len() {
                         let c = self.current_char();
                         if c.is_whitespace() {
                             break;
                         }
                         filename.push(c);
-                        self.index += 1;
+                        self.advance();
+                    }
+                    tokens.push(Spanned::new(
+                        BraincrapToken::String(filename),
+                        self.span_from(filename_start),
+                    ));
+                    if let Some((alias, span)) = self.read_alias() {
+                        tokens.push(Spanned::new(BraincrapToken::Alias(alias), span));
                     }
-                    tokens.push(BraincrapToken::Dollar);
-                    tokens.push(BraincrapToken::String(filename));
                 }
                 '#' => {
-                    self.index += 1;
-                    let macro_name = if ILLEGAL_MACROS.contains(&self.current_char()) {
-                        error!(
-                            "Illegal macro name at index {}: {}",
-                            self.index,
-                            self.current_char()
-                        );
-                        'e' // Default to an arbitrary invalid macro name
-                    } else {
-                        self.current_char()
-                    };
-                    self.index += 1;
-                    self.index += 1;
+                    self.advance();
+                    tokens.push(Spanned::new(BraincrapToken::Hash, self.span_from(start)));
+
+                    let name_start = self.position;
+                    let macro_name = self.read_name()?;
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Name(macro_name),
+                        self.span_from(name_start),
+                    ));
+                    if self.current_char() == '(' {
+                        let arguments_start = self.position;
+                        let parameters = self.read_arguments()?;
+                        tokens.push(Spanned::new(
+                            BraincrapToken::Arguments(parameters),
+                            self.span_from(arguments_start),
+                        ));
+                    }
+
+                    while matches!(self.current_char(), ' ' | '\t') {
+                        self.advance();
+                    }
+                    if self.current_char() == '{' {
+                        let (macro_code, code_span) = self.read_block()?;
+                        tokens.push(Spanned::new(BraincrapToken::String(macro_code), code_span));
+                        continue;
+                    }
+                    let code_start = self.position;
                     let mut macro_code = String::new();
-                    while self.index < self.input.len() as u64 {
+                    while self.index < self.input.len() {
                         let c = self.current_char();
                         if c == '\n' {
                             break;
                         }
                         macro_code.push(c);
-                        self.index += 1;
+                        self.advance();
                     }
-                    tokens.push(BraincrapToken::Hash);
-                    tokens.push(BraincrapToken::Char(macro_name));
-                    tokens.push(BraincrapToken::String(macro_code));
-                    self.index += 1;
+                    tokens.push(Spanned::new(
+                        BraincrapToken::String(macro_code),
+                        self.span_from(code_start),
+                    ));
+                    self.advance();
                 }
-                '+' => {
-                    let mut count: usize = 0;
-                    while self.current_char() == '+' {
-                        self.index += 1;
-                        count += 1;
+                '@' => {
+                    self.advance();
+                    let name_start = self.position;
+                    let c = self.current_char();
+                    if !is_name_char(c) {
+                        self.advance();
+                        return Err(BraincrapError::IllegalMacroName {
+                            name: c,
+                            span: self.span_from(name_start),
+                        });
+                    }
+                    let macro_name = self.read_path();
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Name(macro_name),
+                        self.span_from(start),
+                    ));
+                    if self.current_char() == '(' {
+                        let arguments_start = self.position;
+                        let arguments = self.read_arguments()?;
+                        tokens.push(Spanned::new(
+                            BraincrapToken::Arguments(arguments),
+                            self.span_from(arguments_start),
+                        ));
                     }
-                    tokens.push(BraincrapToken::Plus(count));
+                }
+                '%' if is_name_char(self.input.get(self.index + 1).copied().unwrap_or(' ')) => {
+                    self.advance();
+                    let parameter = self.read_name()?;
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Parameter(parameter),
+                        self.span_from(start),
+                    ));
+                }
+                '+' => {
+                    let count = self.count_run('+');
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Plus(count),
+                        self.span_from(start),
+                    ));
                 }
                 '-' => {
-                    let mut count: usize = 0;
-                    while self.current_char() == '-' {
-                        self.index += 1;
-                        count += 1;
-                    }
-                    tokens.push(BraincrapToken::Minus(count));
+                    let count = self.count_run('-');
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Minus(count),
+                        self.span_from(start),
+                    ));
                 }
                 '<' => {
-                    let mut count: usize = 0;
-                    while self.current_char() == '<' {
-                        self.index += 1;
-                        count += 1;
-                    }
-                    tokens.push(BraincrapToken::Left(count));
+                    let count = self.count_run('<');
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Left(count),
+                        self.span_from(start),
+                    ));
                 }
                 '>' => {
-                    let mut count: usize = 0;
-                    while self.current_char() == '>' {
-                        self.index += 1;
-                        count += 1;
-                    }
-                    tokens.push(BraincrapToken::Right(count));
+                    let count = self.count_run('>');
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Right(count),
+                        self.span_from(start),
+                    ));
                 }
                 '.' => {
-                    let mut count: usize = 0;
-                    while self.current_char() == '.' {
-                        self.index += 1;
-                        count += 1;
-                    }
-                    tokens.push(BraincrapToken::Dot(count));
+                    let count = self.count_run('.');
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Dot(count),
+                        self.span_from(start),
+                    ));
                 }
                 ',' => {
-                    let mut count: usize = 0;
-                    while self.current_char() == ',' {
-                        self.index += 1;
-                        count += 1;
-                    }
-                    tokens.push(BraincrapToken::Comma(count));
+                    let count = self.count_run(',');
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Comma(count),
+                        self.span_from(start),
+                    ));
                 }
                 '[' => {
-                    tokens.push(BraincrapToken::LeftBracket);
-                    self.index += 1;
+                    self.advance();
+                    tokens.push(Spanned::new(
+                        BraincrapToken::LeftBracket,
+                        self.span_from(start),
+                    ));
                 }
                 ']' => {
-                    tokens.push(BraincrapToken::RightBracket);
-                    self.index += 1;
+                    self.advance();
+                    tokens.push(Spanned::new(
+                        BraincrapToken::RightBracket,
+                        self.span_from(start),
+                    ));
                 }
                 ';' => {
                     // Skip comments until a newline is found.
-                    while self.index < self.input.len() as u64 {
+                    while self.index < self.input.len() {
                         let c = self.current_char();
                         if c == '\n' {
                             break;
                         }
-                        self.index += 1;
+                        self.advance();
                     }
                 }
+                _ if self.is_qualified_name() => {
+                    let macro_name = self.read_path();
+                    tokens.push(Spanned::new(
+                        BraincrapToken::Name(macro_name),
+                        self.span_from(start),
+                    ));
+                }
                 _ => {
+                    self.advance();
                     // If the character is not illegal, treat it as a potential macro call.
-                    if !ILLEGAL_MACROS.contains(&self.current_char()) {
-                        tokens.push(BraincrapToken::Char(self.current_char()));
+                    if !ILLEGAL_MACROS.contains(&current_char) {
+                        tokens.push(Spanned::new(
+                            BraincrapToken::Char(current_char),
+                            self.span_from(start),
+                        ));
                     }
-                    self.index += 1;
                 }
             }
         }
-        tokens
+        Ok(tokens)
     }
 
     /// Returns the current character without advancing the position.
     fn current_char(&self) -> char {
-        self.input
-            .chars()
-            .nth(usize::try_from(self.index).unwrap_or(0))
-            .unwrap_or('\0')
+        self.input.get(self.index).copied().unwrap_or('\0')
+    }
+
+    /// Moves past the current character, keeping track of its line and column.
+    fn advance(&mut self) {
+        let Some(&c) = self.input.get(self.index) else {
+            return;
+        };
+        self.index += 1;
+        self.position.start += c.len_utf8();
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+    }
+
+    /// Consumes a macro name: a run of name characters, or a single character that is not
+    /// reserved.
+    fn read_name(&mut self) -> Result<String, BraincrapError> {
+        let start = self.position;
+        let c = self.current_char();
+        if is_name_char(c) {
+            let mut name = String::new();
+            while self.index < self.input.len() && is_name_char(self.current_char()) {
+                name.push(self.current_char());
+                self.advance();
+            }
+            return Ok(name);
+        }
+
+        let at_end = self.index >= self.input.len();
+        self.advance();
+        if at_end || ILLEGAL_MACROS.contains(&c) {
+            return Err(BraincrapError::IllegalMacroName {
+                name: c,
+                span: self.span_from(start),
+            });
+        }
+        Ok(c.to_string())
+    }
+
+    /// Consumes a macro name that may be qualified with namespaces, such as `s::add`.
+    fn read_path(&mut self) -> String {
+        let mut path = String::new();
+        loop {
+            while self.index < self.input.len() && is_name_char(self.current_char()) {
+                path.push(self.current_char());
+                self.advance();
+            }
+            if !self.at_separator() {
+                return path;
+            }
+            path.push_str("::");
+            self.advance();
+            self.advance();
+        }
+    }
+
+    /// Returns whether the input continues with `::` followed by a name.
+    fn at_separator(&self) -> bool {
+        self.separator_at(self.index)
+    }
+
+    /// Returns whether the input has `::` followed by a name at `index`.
+    fn separator_at(&self, index: usize) -> bool {
+        self.input.get(index) == Some(&':')
+            && self.input.get(index + 1) == Some(&':')
+            && self.input.get(index + 2).is_some_and(|&c| is_name_char(c))
+    }
+
+    /// Returns whether the input continues with a qualified name such as `s::add`, which is a
+    /// macro call even without `@`.
+    fn is_qualified_name(&self) -> bool {
+        let length = self.input[self.index..]
+            .iter()
+            .take_while(|&&c| is_name_char(c))
+            .count();
+        length > 0 && self.separator_at(self.index + length)
+    }
+
+    /// Consumes ` as name` after an import, returning the name and its span.
+    fn read_alias(&mut self) -> Option<(String, Span)> {
+        let rest: String = self.input[self.index..]
+            .iter()
+            .take_while(|&&c| c != '\n')
+            .collect();
+        let after_blanks = rest.trim_start_matches([' ', '\t']);
+        let after_as = after_blanks.strip_prefix("as")?;
+        let alias = after_as.trim_start_matches([' ', '\t']);
+        if alias.len() == after_as.len() {
+            return None;
+        }
+        let length = alias.chars().take_while(|&c| is_name_char(c)).count();
+        if length == 0 {
+            return None;
+        }
+
+        let skipped = rest.chars().count() - alias.chars().count();
+        for _ in 0..skipped {
+            self.advance();
+        }
+        let start = self.position;
+        let mut name = String::new();
+        for _ in 0..length {
+            name.push(self.current_char());
+            self.advance();
+        }
+        Some((name, self.span_from(start)))
+    }
+
+    /// Consumes a macro body written between braces, returning the code between them and its
+    /// span. Braces inside comments are ignored.
+    fn read_block(&mut self) -> Result<(String, Span), BraincrapError> {
+        let open = self.position;
+        self.advance();
+
+        let code_start = self.position;
+        let mut code = String::new();
+        let mut depth: usize = 0;
+        loop {
+            if self.index >= self.input.len() {
+                return Err(BraincrapError::UnbalancedBrackets {
+                    bracket: '{',
+                    span: Span {
+                        end: open.start + 1,
+                        ..open
+                    },
+                });
+            }
+            let c = self.current_char();
+            match c {
+                '}' if depth == 0 => break,
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ';' => {
+                    while self.index < self.input.len() && self.current_char() != '\n' {
+                        code.push(self.current_char());
+                        self.advance();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            code.push(c);
+            self.advance();
+        }
+        let span = self.span_from(code_start);
+        self.advance();
+
+        Ok((code, span))
+    }
+
+    /// Consumes a parenthesised argument list, splitting it on the commas that are not nested
+    /// in parentheses or braces.
+    fn read_arguments(&mut self) -> Result<Vec<Spanned<String>>, BraincrapError> {
+        let start = self.position;
+        self.advance();
+
+        let mut arguments = Vec::new();
+        let mut argument = String::new();
+        let mut argument_start = self.position;
+        let mut depth: usize = 0;
+        loop {
+            if self.index >= self.input.len() {
+                return Err(BraincrapError::UnbalancedBrackets {
+                    bracket: '(',
+                    span: Span {
+                        end: start.start + 1,
+                        ..start
+                    },
+                });
+            }
+            let c = self.current_char();
+            self.advance();
+            match c {
+                ')' if depth == 0 => break,
+                ',' if depth == 0 => {
+                    arguments.push((mem::take(&mut argument), argument_start));
+                    argument_start = self.position;
+                }
+                '(' | '{' => {
+                    depth += 1;
+                    argument.push(c);
+                }
+                ')' | '}' => {
+                    depth = depth.saturating_sub(1);
+                    argument.push(c);
+                }
+                _ => argument.push(c),
+            }
+        }
+        if !arguments.is_empty() || !argument.trim().is_empty() {
+            arguments.push((argument, argument_start));
+        }
+
+        Ok(arguments
+            .into_iter()
+            .map(|(raw, start)| {
+                let argument = raw.trim();
+                let mut prefix = raw.len() - raw.trim_start().len();
+                let text = match argument.strip_prefix('{').and_then(|a| a.strip_suffix('}')) {
+                    Some(block) => {
+                        prefix += 1;
+                        block
+                    }
+                    None => argument,
+                };
+                let start = start.skip(&raw[..prefix]);
+                let span = Span {
+                    end: start.start + text.len(),
+                    ..start
+                };
+                Spanned::new(text.to_string(), span)
+            })
+            .collect())
+    }
+
+    /// Consumes a run of identical characters and returns its length.
+    fn count_run(&mut self, c: char) -> usize {
+        let mut count: usize = 0;
+        while self.index < self.input.len() && self.current_char() == c {
+            self.advance();
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns the span from `start` up to the current position.
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.position.start,
+            ..start
+        }
     }
 }
@@ -1,17 +1,32 @@
+use crate::error::BraincrapError;
+use crate::expander::Expander;
+use crate::ir::{lower, Instruction};
+use crate::optimizer::PassManager;
 use crate::parser::BraincrapCommand;
-use log::error;
-use std::collections::HashMap;
+use crate::span::Spanned;
+use crate::tape::{EofBehavior, Overflow, TapeConfig};
 
 #[derive(Debug)]
 pub enum TranspilerArguments {
     C,
     Brainfuck,
+    Rust,
+    Wat,
+    Asm,
+    Llvm,
+    Js,
+    Python,
 }
 
-/// A transpiler that converts Braincrap commands into Brainfuck code.
+/// A transpiler that converts Braincrap commands into Brainfuck, C, Rust, WebAssembly text,
+/// x86-64 assembly, LLVM IR, JavaScript or Python code, picked by `TranspilerArguments`.
 pub struct Transpiler {
-    /// Stores defined macros, mapping their names to their transpiled code.
-    macros: HashMap<char, String>,
+    /// Expands macros and imports before transpiling.
+    expander: Expander,
+    /// Cell width, overflow and EOF semantics honoured by every backend but Brainfuck.
+    tape: TapeConfig,
+    /// The optimizations run before transpiling.
+    passes: PassManager,
 }
 
 impl Default for Transpiler {
@@ -20,128 +35,1819 @@ impl Default for Transpiler {
     }
 }
 
-fn are_braces_balanced(code: &str) -> bool {
-    let mut stack = Vec::new();
+impl Transpiler {
+    /// Creates a new `Transpiler` instance with the default tape configuration.
+    pub fn new() -> Self {
+        Self::with_config(TapeConfig::default())
+    }
+
+    /// Creates a new `Transpiler` instance for the tape described by `tape`.
+    pub fn with_config(tape: TapeConfig) -> Self {
+        Self {
+            expander: Expander::new(),
+            tape,
+            passes: PassManager::default(),
+        }
+    }
+
+    /// Sets the optimizations `transpile` applies.
+    pub fn set_passes(&mut self, passes: PassManager) {
+        self.passes = passes;
+    }
+
+    /// Wraps transpiled C code into a complete program declaring the tape.
+    pub fn c_program(&self, code: &str) -> String {
+        let cell = self.tape.c_type();
+        let scanned = self.tape.cell_bits == 8;
+        // `memrchr` is a GNU extension, only declared with `_GNU_SOURCE`
+        let mut program = if scanned {
+            String::from("#define _GNU_SOURCE\n")
+        } else {
+            String::new()
+        };
+        program.push_str("#include <stdio.h>\n#include <stdint.h>\n#include <string.h>\n");
+
+        let checked = match self.tape.overflow {
+            Overflow::Wrap => false,
+            Overflow::Saturate | Overflow::Error => true,
+        };
+        if checked || scanned {
+            program.push_str("#include <stdlib.h>\n");
+        }
+        if scanned {
+            // Scans that find no zero stop the program instead of leaving the tape
+            program.push_str(&format!(
+                "static {cell} *scan_right({cell} *tape, {cell} *ptr) {{\n\
+                 \t{cell} *zero = memchr(ptr, 0, {} - (ptr - tape));\n\
+                 \tif (zero == NULL) {{ fputs(\"Pointer left the tape\\n\", stderr); exit(1); }}\n\
+                 \treturn zero;\n}}\n\
+                 static {cell} *scan_left({cell} *tape, {cell} *ptr) {{\n\
+                 #ifdef __GLIBC__\n\
+                 \t{cell} *zero = memrchr(tape, 0, ptr - tape + 1);\n\
+                 #else\n\
+                 \t{cell} *zero = ptr;\n\
+                 \twhile (zero != tape && *zero != 0) zero--;\n\
+                 \tif (*zero != 0) zero = NULL;\n\
+                 #endif\n\
+                 \tif (zero == NULL) {{ fputs(\"Pointer left the tape\\n\", stderr); exit(1); }}\n\
+                 \treturn zero;\n}}\n",
+                self.tape.tape_length
+            ));
+        }
+        if checked {
+            program.push_str(&format!(
+                "static void cell_add({cell} *p, long long n) {{\n\tlong long v = (long long)*p + n;\n"
+            ));
+            let (min, max) = (self.tape.min(), self.tape.max());
+            if self.tape.overflow == Overflow::Saturate {
+                program.push_str(&format!(
+                    "\tif (v < {min}LL) v = {min}LL;\n\tif (v > {max}LL) v = {max}LL;\n"
+                ));
+            } else {
+                program.push_str(&format!(
+                    "\tif (v < {min}LL || v > {max}LL) {{ fputs(\"Cell overflowed\\n\", stderr); exit(1); }}\n"
+                ));
+            }
+            program.push_str(&format!("\t*p = ({cell})v;\n}}\n"));
+        }
+
+        program.push_str(&format!(
+            "int main() {{\n\tstatic {cell} tape[{}] = {{0}};\n\t{cell} *ptr = tape;\n\n\t{code}\n\treturn 0;\n}}\n",
+            self.tape.tape_length
+        ));
+        program
+    }
+
+    /// Wraps transpiled Rust code into a standalone `main.rs` declaring the tape.
+    ///
+    /// Output is buffered and flushed before every read, so prompts still show up.
+    pub fn rust_program(&self, code: &str) -> String {
+        let cell = self.tape.rust_type();
+        let mut program = String::from("#![allow(unused)]\nuse std::io::{self, Read, Write};\n\n");
+
+        let (min, max) = (self.tape.min(), self.tape.max());
+        match self.tape.overflow {
+            Overflow::Wrap => {}
+            Overflow::Saturate => program.push_str(&format!(
+                "fn cell_add(cell: &mut {cell}, n: i64) {{\n\
+                 \tlet v = i64::from(*cell).saturating_add(n);\n\
+                 \t*cell = v.clamp({min}, {max}) as {cell};\n}}\n\n"
+            )),
+            // Panicking unwinds `main`, which flushes the output written so far
+            Overflow::Error => program.push_str(&format!(
+                "fn cell_add(cell: &mut {cell}, n: i64) {{\n\
+                 \tlet v = i64::from(*cell).saturating_add(n);\n\
+                 \tif !({min}..={max}).contains(&v) {{ panic!(\"Cell overflowed\"); }}\n\
+                 \t*cell = v as {cell};\n}}\n\n"
+            )),
+        }
+        program.push_str(
+            "fn read(out: &mut impl Write) -> Option<u8> {\n\
+             \tout.flush().unwrap();\n\
+             \tlet mut byte = [0];\n\
+             \tmatch io::stdin().read(&mut byte) {\n\
+             \t\tOk(1) => Some(byte[0]),\n\
+             \t\t_ => None,\n\
+             \t}\n}\n\n",
+        );
+
+        program.push_str(&format!(
+            "fn main() {{\n\tlet mut tape = vec![0{cell}; {}];\n\tlet mut ptr: usize = 0;\n\
+             \tlet mut out = io::BufWriter::new(io::stdout().lock());\n\n\t{code}\n\
+             \tout.flush().unwrap();\n}}\n",
+            self.tape.tape_length
+        ));
+        program
+    }
+
+    /// Wraps transpiled WAT code into a module exporting its tape as `memory` and the program
+    /// as `main`.
+    ///
+    /// The host provides `env.read`, which returns the next byte or -1 on EOF, and `env.write`,
+    /// which writes the lowest byte of its argument.
+    pub fn wat_program(&self, code: &str) -> String {
+        let bytes = self.tape.tape_length * (self.tape.cell_bits / 8) as usize;
+        let pages = bytes.div_ceil(65536).max(1);
+        let mut program = format!(
+            "(module\n  (import \"env\" \"read\" (func $read (result i32)))\n\
+             \x20 (import \"env\" \"write\" (func $write (param i32)))\n\
+             \x20 (memory (export \"memory\") {pages})\n"
+        );
+
+        let (min, max) = (self.tape.min(), self.tape.max());
+        let checked = match self.tape.overflow {
+            Overflow::Wrap => None,
+            Overflow::Saturate => Some(format!(
+                "(if (i64.lt_s (local.get $v) (i64.const {min})) (then (local.set $v (i64.const {min}))))\n    \
+                 (if (i64.gt_s (local.get $v) (i64.const {max})) (then (local.set $v (i64.const {max}))))"
+            )),
+            // Trapping stops the program and reports the error to the host
+            Overflow::Error => Some(format!(
+                "(if (i32.or (i64.lt_s (local.get $v) (i64.const {min})) \
+                 (i64.gt_s (local.get $v) (i64.const {max}))) (then unreachable))"
+            )),
+        };
+        if let Some(check) = checked {
+            program.push_str(&format!(
+                "  (func $cell_add (param $value i32) (param $n i64) (result i32)\n    (local $v i64)\n    \
+                 (local.set $v (i64.add ({} (local.get $value)) (local.get $n)))\n    {check}\n    \
+                 (i32.wrap_i64 (local.get $v)))\n",
+                self.wat_extend()
+            ));
+        }
+
+        program.push_str("  (func (export \"main\")\n    (local $ptr i32)\n    (local $c i32)\n");
+        for line in code.lines() {
+            program.push_str(&format!("    {line}\n"));
+        }
+        program.push_str("  )\n)\n");
+        program
+    }
+
+    /// Wraps transpiled assembly into a complete x86-64 Linux program for `as` and `ld`.
+    ///
+    /// The tape pointer lives in `rbx` and the program talks to the kernel with `read`, `write`
+    /// and `exit` system calls, so it needs no libc. Output is not buffered.
+    pub fn asm_program(&self, code: &str) -> String {
+        let bytes = self.tape.tape_length * (self.tape.cell_bits / 8) as usize;
+        let mut program = format!(
+            "\t.intel_syntax noprefix\n\t.bss\ntape:\n\t.zero {bytes}\ninput:\n\t.zero 1\n\n\
+             \t.text\n\t.globl _start\n_start:\n\tlea rbx, [rip + tape]\n{code}\
+             \tmov eax, 60\n\txor edi, edi\n\tsyscall\n\n"
+        );
+
+        // Writes the byte at `rdi`, the lowest one of a cell
+        program.push_str(
+            "put:\n\tmov rsi, rdi\n\tmov edi, 1\n\tmov edx, 1\n\tmov eax, 1\n\tsyscall\n\tret\n\n",
+        );
+        // Reads a byte into `eax`, or -1 on EOF
+        program.push_str(
+            "get:\n\txor edi, edi\n\tlea rsi, [rip + input]\n\tmov edx, 1\n\txor eax, eax\n\
+             \tsyscall\n\tcmp rax, 1\n\tjne 1f\n\tmovzx eax, byte ptr [rip + input]\n\tret\n\
+             1:\n\tmov eax, -1\n\tret\n",
+        );
+
+        let (min, max) = (self.tape.min(), self.tape.max());
+        let check = match self.tape.overflow {
+            Overflow::Wrap => return program,
+            Overflow::Saturate => format!(
+                "\tmov rcx, {min}\n\tcmp rax, rcx\n\tcmovl rax, rcx\n\
+                 \tmov rcx, {max}\n\tcmp rax, rcx\n\tcmovg rax, rcx\n"
+            ),
+            Overflow::Error => format!(
+                "\tmov rcx, {min}\n\tcmp rax, rcx\n\tjl 1f\n\
+                 \tmov rcx, {max}\n\tcmp rax, rcx\n\tjg 1f\n"
+            ),
+        };
+        // Adds `rsi` to the cell at `rdi`
+        program.push_str(&format!(
+            "\ncell_add:\n{}\tadd rax, rsi\n{check}\tmov {}, {}\n\tret\n",
+            self.asm_widen("rax", &self.asm_cell_at("rdi")),
+            self.asm_cell_at("rdi"),
+            self.asm_accumulator(),
+        ));
+        if self.tape.overflow == Overflow::Error {
+            program.push_str(
+                "1:\n\tmov edi, 2\n\tlea rsi, [rip + overflowed]\n\tmov edx, 16\n\tmov eax, 1\n\
+                 \tsyscall\n\tmov eax, 60\n\tmov edi, 1\n\tsyscall\n\n\
+                 \t.section .rodata\noverflowed:\n\t.ascii \"Cell overflowed\\n\"\n",
+            );
+        }
+        program
+    }
+
+    /// Wraps transpiled LLVM IR into a module with the tape as a global array and a `main`
+    /// function.
+    ///
+    /// The pointer lives in an `alloca` that `mem2reg` turns into SSA values, and the program
+    /// reads and writes with `getchar` and `putchar` from libc. Pointers are opaque, which LLVM
+    /// reads by default since version 15.
+    pub fn llvm_program(&self, code: &str) -> String {
+        let cell = self.llvm_type();
+        let mut program = format!(
+            "@tape = internal global [{} x {cell}] zeroinitializer\n\n\
+             declare i32 @putchar(i32)\ndeclare i32 @getchar()\n",
+            self.tape.tape_length
+        );
+
+        let (min, max) = (self.tape.min(), self.tape.max());
+        let extend = self.llvm_extend();
+        let store =
+            format!("  %r = trunc i64 %v to {cell}\n  store {cell} %r, ptr %p\n  ret void\n");
+        let body = match self.tape.overflow {
+            Overflow::Wrap => None,
+            Overflow::Saturate => Some(format!(
+                "  %low = icmp slt i64 %s, {min}\n  %l = select i1 %low, i64 {min}, i64 %s\n\
+                 \x20 %high = icmp sgt i64 %l, {max}\n  %v = select i1 %high, i64 {max}, i64 %l\n{store}"
+            )),
+            Overflow::Error => {
+                program.push_str(
+                    "declare i64 @write(i32, ptr, i64)\ndeclare void @exit(i32)\n\n\
+                     @overflowed = private constant [16 x i8] c\"Cell overflowed\\0A\"\n",
+                );
+                Some(format!(
+                    "  %low = icmp slt i64 %s, {min}\n  %high = icmp sgt i64 %s, {max}\n\
+                     \x20 %out = or i1 %low, %high\n  br i1 %out, label %overflow, label %fits\n\
+                     fits:\n  %v = add i64 %s, 0\n{store}\
+                     overflow:\n  %w = call i64 @write(i32 2, ptr @overflowed, i64 16)\n\
+                     \x20 call void @exit(i32 1)\n  unreachable\n"
+                ))
+            }
+        };
+        if let Some(body) = body {
+            program.push_str(&format!(
+                "\ndefine internal void @cell_add(ptr %p, i64 %n) {{\n\
+                 entry:\n  %c = load {cell}, ptr %p\n  %e = {extend} {cell} %c to i64\n\
+                 \x20 %s = add i64 %e, %n\n{body}}}\n"
+            ));
+        }
+
+        program.push_str(&format!(
+            "\ndefine i32 @main() {{\nentry:\n  %ptr = alloca ptr\n  store ptr @tape, ptr %ptr\n\
+             {code}  ret i32 0\n}}\n"
+        ));
+        program
+    }
+
+    /// Wraps transpiled JavaScript into `execute(input)`, which runs the program on a
+    /// `Uint8Array` and returns its output as another one, and `run(inputString)`, which does the
+    /// same with UTF-8 strings.
+    ///
+    /// When the program fails, such as a cell overflowing with `Overflow::Error`, the thrown error
+    /// carries what was output before it as `error.output`.
+    ///
+    /// The tape is a typed array as wide as a cell, storing into it wraps the value.
+    pub fn js_program(&self, code: &str) -> String {
+        let array = format!(
+            "{}{}Array",
+            if self.tape.signed { "Int" } else { "Uint" },
+            self.tape.cell_bits
+        );
+        let (min, max) = (self.tape.min(), self.tape.max());
+        let check = match self.tape.overflow {
+            Overflow::Wrap => String::new(),
+            Overflow::Saturate => format!(
+                "\tfunction cellAdd(index, n) {{\n\
+                 \t\tconst v = tape[index] + n;\n\
+                 \t\ttape[index] = v < {min} ? {min} : v > {max} ? {max} : v;\n\t}}\n"
+            ),
+            Overflow::Error => format!(
+                "\tfunction cellAdd(index, n) {{\n\
+                 \t\tconst v = tape[index] + n;\n\
+                 \t\tif (v < {min} || v > {max}) throw new RangeError(\"Cell overflowed\");\n\
+                 \t\ttape[index] = v;\n\t}}\n"
+            ),
+        };
+        format!(
+            "function execute(input) {{\n\tconst tape = new {array}({});\n\
+             \tconst output = [];\n\tlet ptr = 0;\n\tlet pos = 0;\n{check}\n\
+             \ttry {{\n\t\t{code}\n\t}} catch (error) {{\n\
+             \t\terror.output = Uint8Array.from(output);\n\t\tthrow error;\n\t}}\n\
+             \treturn Uint8Array.from(output);\n}}\n\n\
+             function run(inputString) {{\n\
+             \treturn new TextDecoder().decode(execute(new TextEncoder().encode(inputString)));\n}}\n",
+            self.tape.tape_length
+        )
+    }
+
+    /// Wraps transpiled JavaScript like `js_program`, and runs it on stdin when started with
+    /// Node. The output of a failing program is written before the error is reported.
+    pub fn node_program(&self, code: &str) -> String {
+        let mut program = self.js_program(code);
+        program.push_str(
+            "\nif (typeof require !== \"undefined\" && require.main === module) {\n\
+             \ttry {\n\
+             \t\tprocess.stdout.write(execute(require(\"fs\").readFileSync(0)));\n\
+             \t} catch (error) {\n\
+             \t\tif (error.output) process.stdout.write(error.output);\n\t\tthrow error;\n\t}\n}\n",
+        );
+        program
+    }
+
+    /// Wraps transpiled Python into a Python 3 script reading `sys.stdin.buffer` and writing
+    /// `sys.stdout.buffer`.
+    ///
+    /// Byte cells live in a `bytearray`, anything else in a list of integers.
+    pub fn python_program(&self, code: &str) -> String {
+        let tape = if self.is_byte_tape() {
+            format!("bytearray({})", self.tape.tape_length)
+        } else {
+            format!("[0] * {}", self.tape.tape_length)
+        };
+        let mut program = String::from("import sys\n\n\n");
+        if self.tape.overflow == Overflow::Error {
+            program.push_str(&format!(
+                "def check(value):\n    if not {} <= value <= {}:\n        \
+                 raise OverflowError(\"Cell overflowed\")\n    return value\n\n\n",
+                self.tape.min(),
+                self.tape.max()
+            ));
+        }
+        // Searching raises `ValueError` instead of leaving the tape
+        program.push_str(
+            "def scan_right(tape, ptr):\n    try:\n        return tape.index(0, ptr)\n    \
+             except ValueError:\n        \
+             raise IndexError(\"Pointer left the tape\") from None\n\n\n",
+        );
+        if self.is_byte_tape() {
+            program.push_str(
+                "def scan_left(tape, ptr):\n    try:\n        return tape.rindex(0, 0, ptr + 1)\n    \
+                 except ValueError:\n        \
+                 raise IndexError(\"Pointer left the tape\") from None\n\n\n",
+            );
+        }
+        // Output is flushed before every read, so prompts still show up
+        program.push_str(
+            "def read(out):\n    out.flush()\n    byte = sys.stdin.buffer.read(1)\n    \
+             return byte[0] if byte else None\n\n\n",
+        );
+        program.push_str(&format!(
+            "def main():\n    tape = {tape}\n    ptr = 0\n    out = sys.stdout.buffer\n"
+        ));
+        for line in code.lines() {
+            program.push_str(&format!("    {line}\n"));
+        }
+        program.push_str("    out.flush()\n\n\nmain()\n");
+        program
+    }
+
+    /// Transpiles adding `delta` to the cell at `offset` into C.
+    fn c_add(&self, offset: isize, delta: i128) -> String {
+        let cell = c_cell(offset);
+        match self.tape.overflow {
+            Overflow::Wrap if self.tape.signed => {
+                // Signed overflow is undefined in C, so do the arithmetic unsigned
+                let unsigned = format!("uint{}_t", self.tape.cell_bits);
+                format!(
+                    "({cell} = ({})(({unsigned}){cell} + ({unsigned}){delta}));",
+                    self.tape.c_type()
+                )
+            }
+            Overflow::Wrap if delta < 0 => format!("({cell} -= {});", -delta),
+            Overflow::Wrap => format!("({cell} += {delta});"),
+            Overflow::Saturate | Overflow::Error => {
+                format!("cell_add({}, {delta});", c_address(offset))
+            }
+        }
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into C.
+    fn c_mul_add(&self, src: isize, dst: isize, factor: i64) -> String {
+        let (src, dst_cell) = (c_cell(src), c_cell(dst));
+        // The product is computed in 32 bits so it cannot overflow after integer promotion,
+        // which keeps the lowest bits of the cell exact
+        let operator = if factor < 0 { '-' } else { '+' };
+        let magnitude = factor.unsigned_abs() as u32;
+        match self.tape.overflow {
+            Overflow::Wrap if self.tape.signed => format!(
+                "({dst_cell} = ({})((uint32_t){dst_cell} {operator} (uint32_t){src} * {magnitude}u));",
+                self.tape.c_type()
+            ),
+            Overflow::Wrap if magnitude == 1 => format!("({dst_cell} {operator}= {src});"),
+            Overflow::Wrap => {
+                format!("({dst_cell} {operator}= (uint32_t){src} * {magnitude}u);")
+            }
+            Overflow::Saturate | Overflow::Error => format!(
+                "cell_add({}, (long long){src} * {factor}LL);",
+                c_address(dst)
+            ),
+        }
+    }
+
+    /// Transpiles moving the pointer by `step` until it reaches a 0 into C.
+    ///
+    /// Byte cells are searched by the `scan_right` and `scan_left` helpers of `c_program`,
+    /// anything else moves one step at a time.
+    fn c_scan(&self, step: isize) -> String {
+        match step {
+            1 if self.tape.cell_bits == 8 => "(ptr = scan_right(tape, ptr));".to_string(),
+            -1 if self.tape.cell_bits == 8 => "(ptr = scan_left(tape, ptr));".to_string(),
+            _ => format!("while(*ptr != 0){{{}}}", c_move(step)),
+        }
+    }
+
+    /// Transpiles a single read into the cell at `offset` into C.
+    fn c_input(&self, offset: isize) -> String {
+        let cell = c_cell(offset);
+        match self.tape.eof {
+            EofBehavior::MinusOne => format!("({cell} = getchar());"),
+            EofBehavior::Zero => format!("{{int c = getchar(); {cell} = c == EOF ? 0 : c;}}"),
+            EofBehavior::Unchanged => format!("{{int c = getchar(); if(c != EOF){{{cell} = c;}}}}"),
+        }
+    }
+
+    /// Transpiles adding `delta` to the cell at `offset` into Rust.
+    fn rust_add(&self, offset: isize, delta: i64) -> String {
+        let cell = rust_cell(offset);
+        match self.tape.overflow {
+            Overflow::Wrap if delta < 0 => format!(
+                "{cell} = {cell}.wrapping_sub({});",
+                self.tape.wrap(-i128::from(delta))
+            ),
+            Overflow::Wrap => format!(
+                "{cell} = {cell}.wrapping_add({});",
+                self.tape.wrap(i128::from(delta))
+            ),
+            Overflow::Saturate | Overflow::Error => format!("cell_add(&mut {cell}, {delta});"),
+        }
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into Rust.
+    fn rust_mul_add(&self, src: isize, dst: isize, factor: i64) -> String {
+        let (src, dst) = (rust_cell(src), rust_cell(dst));
+        let (method, magnitude) = if factor < 0 {
+            ("wrapping_sub", self.tape.wrap(-i128::from(factor)))
+        } else {
+            ("wrapping_add", self.tape.wrap(i128::from(factor)))
+        };
+        match self.tape.overflow {
+            Overflow::Wrap if factor.unsigned_abs() == 1 => {
+                format!("{dst} = {dst}.{method}({src});")
+            }
+            Overflow::Wrap => format!("{dst} = {dst}.{method}({src}.wrapping_mul({magnitude}));"),
+            // The product is computed before borrowing the destination mutably
+            Overflow::Saturate | Overflow::Error => format!(
+                "{{let n = i64::from({src}).saturating_mul({factor}); cell_add(&mut {dst}, n);}}"
+            ),
+        }
+    }
+
+    /// Transpiles moving the pointer by `step` until it reaches a 0 into Rust.
+    ///
+    /// Steps of one cell search the tape with `position` and `rposition`, anything else moves
+    /// one step at a time. Either way, leaving the tape panics.
+    fn rust_scan(step: isize) -> String {
+        match step {
+            1 => "ptr += tape[ptr..].iter().position(|&c| c == 0).expect(\"Pointer left the tape\");"
+                .to_string(),
+            -1 => "ptr = tape[..=ptr].iter().rposition(|&c| c == 0).expect(\"Pointer left the tape\");"
+                .to_string(),
+            _ => format!("while tape[ptr] != 0 {{{}}}", rust_move(step)),
+        }
+    }
+
+    /// Transpiles a single read into the cell at `offset` into Rust.
+    fn rust_input(&self, offset: isize) -> String {
+        let cell = rust_cell(offset);
+        let ty = self.tape.rust_type();
+        match self.tape.eof {
+            EofBehavior::MinusOne => format!(
+                "{cell} = read(&mut out).map_or({}, |b| b as {ty});",
+                self.tape.wrap(-1)
+            ),
+            EofBehavior::Zero => format!("{cell} = read(&mut out).map_or(0, |b| b as {ty});"),
+            EofBehavior::Unchanged => {
+                format!("if let Some(b) = read(&mut out) {{{cell} = b as {ty};}}")
+            }
+        }
+    }
+
+    /// The WAT instruction widening a loaded cell to 64 bits.
+    fn wat_extend(&self) -> &'static str {
+        if self.tape.signed {
+            "i64.extend_i32_s"
+        } else {
+            "i64.extend_i32_u"
+        }
+    }
+
+    /// The WAT expression loading the cell at `offset`.
+    fn wat_load(&self, offset: isize) -> String {
+        let load = match (self.tape.cell_bits, self.tape.signed) {
+            (8, true) => "i32.load8_s",
+            (8, false) => "i32.load8_u",
+            (16, true) => "i32.load16_s",
+            (16, false) => "i32.load16_u",
+            _ => "i32.load",
+        };
+        format!("({load}{})", self.wat_address(offset))
+    }
 
-    for c in code.chars() {
-        match c {
-            '[' => stack.push(c),
-            ']' => {
-                if stack.pop().is_none() {
-                    return false;
+    /// The WAT instruction storing `value` into the cell at `offset`.
+    fn wat_store(&self, offset: isize, value: &str) -> String {
+        let store = match self.tape.cell_bits {
+            8 => "i32.store8",
+            16 => "i32.store16",
+            _ => "i32.store",
+        };
+        format!("({store}{} {value})", self.wat_address(offset))
+    }
+
+    /// The memory argument and address of the cell at `offset`.
+    ///
+    /// Static offsets cannot be negative, so cells to the left are addressed by subtracting.
+    fn wat_address(&self, offset: isize) -> String {
+        let bytes = offset * (self.tape.cell_bits / 8) as isize;
+        match bytes {
+            0 => " (local.get $ptr)".to_string(),
+            _ if bytes < 0 => format!(" (i32.sub (local.get $ptr) (i32.const {}))", -bytes),
+            _ => format!(" offset={bytes} (local.get $ptr)"),
+        }
+    }
+
+    /// Transpiles moving the pointer by `distance` cells into WAT.
+    fn wat_move(&self, distance: isize) -> String {
+        let bytes = distance * (self.tape.cell_bits / 8) as isize;
+        if bytes < 0 {
+            format!(
+                "(local.set $ptr (i32.sub (local.get $ptr) (i32.const {})))",
+                -bytes
+            )
+        } else {
+            format!("(local.set $ptr (i32.add (local.get $ptr) (i32.const {bytes})))")
+        }
+    }
+
+    /// Transpiles adding `delta` to the cell at `offset` into WAT.
+    fn wat_add(&self, offset: isize, delta: i64) -> String {
+        let cell = self.wat_load(offset);
+        let value = match self.tape.overflow {
+            // Stores keep the lowest bits, which wraps the result
+            Overflow::Wrap if delta < 0 => format!(
+                "(i32.sub {cell} (i32.const {}))",
+                self.tape.wrap(-i128::from(delta))
+            ),
+            Overflow::Wrap => format!(
+                "(i32.add {cell} (i32.const {}))",
+                self.tape.wrap(i128::from(delta))
+            ),
+            Overflow::Saturate | Overflow::Error => {
+                format!("(call $cell_add {cell} (i64.const {delta}))")
+            }
+        };
+        self.wat_store(offset, &value)
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into WAT.
+    fn wat_mul_add(&self, src: isize, dst: isize, factor: i64) -> String {
+        let (src, dst_cell) = (self.wat_load(src), self.wat_load(dst));
+        let (operator, magnitude) = if factor < 0 {
+            ("i32.sub", self.tape.wrap(-i128::from(factor)))
+        } else {
+            ("i32.add", self.tape.wrap(i128::from(factor)))
+        };
+        let value = match self.tape.overflow {
+            Overflow::Wrap if factor.unsigned_abs() == 1 => {
+                format!("({operator} {dst_cell} {src})")
+            }
+            Overflow::Wrap => {
+                format!("({operator} {dst_cell} (i32.mul {src} (i32.const {magnitude})))")
+            }
+            Overflow::Saturate | Overflow::Error => format!(
+                "(call $cell_add {dst_cell} (i64.mul ({} {src}) (i64.const {factor})))",
+                self.wat_extend()
+            ),
+        };
+        self.wat_store(dst, &value)
+    }
+
+    /// Transpiles a single read into the cell at `offset` into WAT.
+    fn wat_input(&self, offset: isize) -> String {
+        match self.tape.eof {
+            // -1 is stored as every bit set
+            EofBehavior::MinusOne => self.wat_store(offset, "(call $read)"),
+            EofBehavior::Zero => format!(
+                "(local.set $c (call $read))\n{}",
+                self.wat_store(
+                    offset,
+                    "(select (i32.const 0) (local.get $c) (i32.lt_s (local.get $c) (i32.const 0)))"
+                )
+            ),
+            EofBehavior::Unchanged => format!(
+                "(local.set $c (call $read))\n(if (i32.ge_s (local.get $c) (i32.const 0)) (then {}))",
+                self.wat_store(offset, "(local.get $c)")
+            ),
+        }
+    }
+
+    /// The address of the cell at `offset` in assembly.
+    fn asm_address(&self, offset: isize) -> String {
+        let bytes = offset * (self.tape.cell_bits / 8) as isize;
+        match bytes {
+            0 => "rbx".to_string(),
+            _ if bytes < 0 => format!("rbx - {}", -bytes),
+            _ => format!("rbx + {bytes}"),
+        }
+    }
+
+    /// The assembly operand for the cell at `address`.
+    fn asm_cell_at(&self, address: &str) -> String {
+        let size = match self.tape.cell_bits {
+            8 => "byte",
+            16 => "word",
+            _ => "dword",
+        };
+        format!("{size} ptr [{address}]")
+    }
+
+    /// The assembly operand for the cell at `offset`.
+    fn asm_cell(&self, offset: isize) -> String {
+        self.asm_cell_at(&self.asm_address(offset))
+    }
+
+    /// The part of `rax` as wide as a cell.
+    fn asm_accumulator(&self) -> &'static str {
+        match self.tape.cell_bits {
+            8 => "al",
+            16 => "ax",
+            _ => "eax",
+        }
+    }
+
+    /// Loads the cell `operand` into the 64-bit `register`, sign extending signed cells.
+    fn asm_widen(&self, register: &str, operand: &str) -> String {
+        match (self.tape.cell_bits, self.tape.signed) {
+            (32, true) => format!("\tmovsxd {register}, {operand}\n"),
+            // Writing the lower half of a register clears the upper one
+            (32, false) => format!("\tmov {}, {operand}\n", register.replacen('r', "e", 1)),
+            (_, true) => format!("\tmovsx {register}, {operand}\n"),
+            (_, false) => format!("\tmovzx {register}, {operand}\n"),
+        }
+    }
+
+    /// Transpiles adding `delta` to the cell at `offset` into assembly.
+    fn asm_add(&self, offset: isize, delta: i64) -> String {
+        let cell = self.asm_cell(offset);
+        match self.tape.overflow {
+            Overflow::Wrap if delta < 0 => {
+                format!("\tsub {cell}, {}\n", self.tape.wrap(-i128::from(delta)))
+            }
+            Overflow::Wrap => format!("\tadd {cell}, {}\n", self.tape.wrap(i128::from(delta))),
+            Overflow::Saturate | Overflow::Error => format!(
+                "\tlea rdi, [{}]\n\tmov rsi, {delta}\n\tcall cell_add\n",
+                self.asm_address(offset)
+            ),
+        }
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into assembly.
+    fn asm_mul_add(&self, src: isize, dst: isize, factor: i64) -> String {
+        let src_cell = self.asm_cell(src);
+        match self.tape.overflow {
+            Overflow::Wrap => {
+                let operator = if factor < 0 { "sub" } else { "add" };
+                // Only the lowest bits of the product end up in the cell
+                let magnitude = factor.unsigned_abs() as u32 as i32;
+                let mut code = if self.tape.cell_bits == 32 {
+                    format!("\tmov eax, {src_cell}\n")
+                } else {
+                    format!("\tmovzx eax, {src_cell}\n")
+                };
+                if magnitude != 1 {
+                    code.push_str(&format!("\timul eax, eax, {magnitude}\n"));
                 }
+                code.push_str(&format!(
+                    "\t{operator} {}, {}\n",
+                    self.asm_cell(dst),
+                    self.asm_accumulator()
+                ));
+                code
             }
-            _ => {}
+            Overflow::Saturate | Overflow::Error => format!(
+                "{}\tmov rax, {factor}\n\timul rsi, rax\n\tlea rdi, [{}]\n\tcall cell_add\n",
+                self.asm_widen("rsi", &src_cell),
+                self.asm_address(dst)
+            ),
         }
     }
 
-    stack.is_empty()
-}
+    /// Transpiles a single read into the cell at `offset` into assembly.
+    fn asm_input(&self, offset: isize) -> String {
+        let store = format!(
+            "\tmov {}, {}\n",
+            self.asm_cell(offset),
+            self.asm_accumulator()
+        );
+        match self.tape.eof {
+            // -1 is stored as every bit set
+            EofBehavior::MinusOne => format!("\tcall get\n{store}"),
+            EofBehavior::Zero => {
+                format!("\tcall get\n\ttest eax, eax\n\tjns 1f\n\txor eax, eax\n1:\n{store}")
+            }
+            EofBehavior::Unchanged => format!("\tcall get\n\ttest eax, eax\n\tjs 1f\n{store}1:\n"),
+        }
+    }
 
-impl Transpiler {
-    /// Creates a new `Transpiler` instance.
-    pub fn new() -> Self {
-        Self {
-            macros: HashMap::new(),
+    /// The LLVM type of a single cell.
+    fn llvm_type(&self) -> String {
+        format!("i{}", self.tape.cell_bits)
+    }
+
+    /// The LLVM instruction widening a cell, sign extending signed cells.
+    fn llvm_extend(&self) -> &'static str {
+        if self.tape.signed {
+            "sext"
+        } else {
+            "zext"
+        }
+    }
+
+    /// Emits the LLVM IR computing a pointer to the cell at `offset` and returns its name.
+    fn llvm_cell(&self, offset: isize, names: &mut usize, output: &mut String) -> String {
+        let base = llvm_name(names);
+        output.push_str(&format!("  {base} = load ptr, ptr %ptr\n"));
+        if offset == 0 {
+            return base;
+        }
+        let cell = llvm_name(names);
+        output.push_str(&format!(
+            "  {cell} = getelementptr {}, ptr {base}, i64 {offset}\n",
+            self.llvm_type()
+        ));
+        cell
+    }
+
+    /// Emits the LLVM IR loading the cell at `pointer` and returns its name.
+    fn llvm_load(&self, pointer: &str, names: &mut usize, output: &mut String) -> String {
+        let value = llvm_name(names);
+        output.push_str(&format!(
+            "  {value} = load {}, ptr {pointer}\n",
+            self.llvm_type()
+        ));
+        value
+    }
+
+    /// Emits the LLVM IR widening the cell `value` to the integer type `to` and returns its name.
+    fn llvm_widen(&self, value: &str, to: &str, names: &mut usize, output: &mut String) -> String {
+        let cell = self.llvm_type();
+        if cell == to {
+            return value.to_string();
+        }
+        let wide = llvm_name(names);
+        output.push_str(&format!(
+            "  {wide} = {} {cell} {value} to {to}\n",
+            self.llvm_extend()
+        ));
+        wide
+    }
+
+    /// Transpiles adding `delta` to the cell at `offset` into LLVM IR.
+    fn llvm_add(&self, offset: isize, delta: i64, names: &mut usize, output: &mut String) {
+        let cell = self.llvm_cell(offset, names, output);
+        match self.tape.overflow {
+            Overflow::Wrap => {
+                let value = self.llvm_load(&cell, names, output);
+                let sum = llvm_name(names);
+                let ty = self.llvm_type();
+                output.push_str(&format!(
+                    "  {sum} = add {ty} {value}, {}\n  store {ty} {sum}, ptr {cell}\n",
+                    self.tape.wrap(i128::from(delta))
+                ));
+            }
+            Overflow::Saturate | Overflow::Error => {
+                output.push_str(&format!("  call void @cell_add(ptr {cell}, i64 {delta})\n"));
+            }
+        }
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into LLVM IR.
+    fn llvm_mul_add(
+        &self,
+        src: isize,
+        dst: isize,
+        factor: i64,
+        names: &mut usize,
+        output: &mut String,
+    ) {
+        let src = self.llvm_cell(src, names, output);
+        let src = self.llvm_load(&src, names, output);
+        let dst = self.llvm_cell(dst, names, output);
+        let product = llvm_name(names);
+        match self.tape.overflow {
+            Overflow::Wrap => {
+                let ty = self.llvm_type();
+                let value = self.llvm_load(&dst, names, output);
+                let sum = llvm_name(names);
+                output.push_str(&format!(
+                    "  {product} = mul {ty} {src}, {}\n  {sum} = add {ty} {value}, {product}\n\
+                     \x20 store {ty} {sum}, ptr {dst}\n",
+                    self.tape.wrap(i128::from(factor))
+                ));
+            }
+            Overflow::Saturate | Overflow::Error => {
+                let src = self.llvm_widen(&src, "i64", names, output);
+                output.push_str(&format!(
+                    "  {product} = mul i64 {src}, {factor}\n  call void @cell_add(ptr {dst}, i64 {product})\n"
+                ));
+            }
+        }
+    }
+
+    /// Transpiles a single read into the cell at `offset` into LLVM IR.
+    fn llvm_input(&self, offset: isize, names: &mut usize, output: &mut String) {
+        let cell = self.llvm_cell(offset, names, output);
+        let read = llvm_name(names);
+        output.push_str(&format!("  {read} = call i32 @getchar()\n"));
+        let ty = self.llvm_type();
+        // -1 is stored as every bit set
+        let mut value = read.clone();
+        if ty != "i32" {
+            value = llvm_name(names);
+            output.push_str(&format!("  {value} = trunc i32 {read} to {ty}\n"));
+        }
+        let fallback = match self.tape.eof {
+            EofBehavior::MinusOne => None,
+            EofBehavior::Zero => Some("0".to_string()),
+            EofBehavior::Unchanged => Some(self.llvm_load(&cell, names, output)),
+        };
+        if let Some(fallback) = fallback {
+            let eof = llvm_name(names);
+            let chosen = llvm_name(names);
+            output.push_str(&format!(
+                "  {eof} = icmp slt i32 {read}, 0\n  {chosen} = select i1 {eof}, {ty} {fallback}, {ty} {value}\n"
+            ));
+            value = chosen;
+        }
+        output.push_str(&format!("  store {ty} {value}, ptr {cell}\n"));
+    }
+
+    /// Transpiles moving the pointer by `distance` cells into LLVM IR.
+    fn llvm_move(&self, distance: isize, names: &mut usize, output: &mut String) {
+        let cell = self.llvm_cell(distance, names, output);
+        output.push_str(&format!("  store ptr {cell}, ptr %ptr\n"));
+    }
+
+    /// Emits the LLVM IR branching to `body` while the current cell is not 0, and to `end`
+    /// otherwise.
+    fn llvm_condition(&self, body: &str, end: &str, names: &mut usize, output: &mut String) {
+        let cell = self.llvm_cell(0, names, output);
+        let value = self.llvm_load(&cell, names, output);
+        let nonzero = llvm_name(names);
+        output.push_str(&format!(
+            "  {nonzero} = icmp ne {} {value}, 0\n  br i1 {nonzero}, label %{body}, label %{end}\n",
+            self.llvm_type()
+        ));
+    }
+
+    /// Transpiles adding `delta` to the cell at `offset` into JavaScript.
+    fn js_add(&self, offset: isize, delta: i64) -> String {
+        match self.tape.overflow {
+            Overflow::Wrap if delta < 0 => format!(
+                "{} -= {};",
+                js_cell(offset),
+                self.tape.wrap(-i128::from(delta))
+            ),
+            Overflow::Wrap => format!(
+                "{} += {};",
+                js_cell(offset),
+                self.tape.wrap(i128::from(delta))
+            ),
+            Overflow::Saturate | Overflow::Error => {
+                format!("cellAdd({}, {delta});", js_index(offset))
+            }
+        }
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into JavaScript.
+    fn js_mul_add(&self, src: isize, dst: isize, factor: i64) -> String {
+        let src = js_cell(src);
+        match self.tape.overflow {
+            // `Math.imul` keeps the lowest 32 bits exact, where a float product could round
+            Overflow::Wrap => format!("{} += Math.imul({src}, {});", js_cell(dst), factor as i32),
+            Overflow::Saturate | Overflow::Error => {
+                format!("cellAdd({}, {src} * {factor});", js_index(dst))
+            }
+        }
+    }
+
+    /// Transpiles a single read into the cell at `offset` into JavaScript.
+    fn js_input(&self, offset: isize) -> String {
+        let cell = js_cell(offset);
+        match self.tape.eof {
+            EofBehavior::MinusOne => format!("{cell} = pos < input.length ? input[pos++] : -1;"),
+            EofBehavior::Zero => format!("{cell} = pos < input.length ? input[pos++] : 0;"),
+            EofBehavior::Unchanged => format!("if (pos < input.length) {cell} = input[pos++];"),
         }
     }
 
-    /// Transpiles a vector of `BraincrapCommand`s into either Brainfuck or C.
+    /// Whether the cells fit in a `bytearray`.
+    fn is_byte_tape(&self) -> bool {
+        self.tape.cell_bits == 8 && !self.tape.signed
+    }
+
+    /// The Python expression storing `value` into a cell with the tape's overflow semantics.
+    fn python_value(&self, value: &str) -> String {
+        let (min, max) = (self.tape.min(), self.tape.max());
+        match self.tape.overflow {
+            Overflow::Wrap => self.python_wrap(value),
+            Overflow::Saturate => format!("min(max({value}, {min}), {max})"),
+            Overflow::Error => format!("check({value})"),
+        }
+    }
+
+    /// The Python expression wrapping `value` into the range of a cell.
+    fn python_wrap(&self, value: &str) -> String {
+        let mask = (1i64 << self.tape.cell_bits) - 1;
+        if self.tape.signed {
+            let half = 1i64 << (self.tape.cell_bits - 1);
+            format!("(({value} + {half}) & {mask}) - {half}")
+        } else {
+            format!("({value}) & {mask}")
+        }
+    }
+
+    /// Transpiles adding the cell at `src` times `factor` to the cell at `dst` into Python.
+    fn python_mul_add(&self, src: isize, dst: isize, factor: i64) -> String {
+        let (src, dst) = (python_cell(src), python_cell(dst));
+        let sum = match factor {
+            1 => format!("{dst} + {src}"),
+            -1 => format!("{dst} - {src}"),
+            _ if factor < 0 => format!("{dst} - {src} * {}", factor.unsigned_abs()),
+            _ => format!("{dst} + {src} * {factor}"),
+        };
+        format!("{dst} = {}", self.python_value(&sum))
+    }
+
+    /// Transpiles a single read into the cell at `offset` into Python, one line per statement.
+    fn python_input(&self, offset: isize) -> Vec<String> {
+        let cell = python_cell(offset);
+        let byte = if self.tape.signed {
+            self.python_wrap("byte")
+        } else {
+            "byte".to_string()
+        };
+        let store = match self.tape.eof {
+            EofBehavior::MinusOne => format!(
+                "{cell} = {} if byte is None else {byte}",
+                self.tape.wrap(-1)
+            ),
+            EofBehavior::Zero => format!("{cell} = 0 if byte is None else {byte}"),
+            EofBehavior::Unchanged => {
+                return vec![
+                    "byte = read(out)".to_string(),
+                    "if byte is not None:".to_string(),
+                    format!("    {cell} = {byte}"),
+                ]
+            }
+        };
+        vec!["byte = read(out)".to_string(), store]
+    }
+
+    /// Transpiles a vector of `BraincrapCommand`s into any of the `TranspilerArguments` targets.
+    ///
+    /// # Errors
+    /// Returns an error if an undefined macro is run or the loops are not balanced once all
+    /// macros and imports are expanded.
     pub fn transpile(
         &mut self,
-        commands: Vec<BraincrapCommand>,
+        commands: Vec<Spanned<BraincrapCommand>>,
         args: &TranspilerArguments,
-    ) -> String {
-        match args {
-            TranspilerArguments::Brainfuck => self.transpile_brainfuck(commands),
-            TranspilerArguments::C => self.transpile_c(commands),
-        }
+    ) -> Result<String, BraincrapError> {
+        let program = self.expander.expand(commands)?;
+        let program = self.passes.run(lower(&program)?, &self.tape);
+        self.transpile_instructions(&program, args)
     }
 
-    /// Transpiles to Brainfuck
-    fn transpile_brainfuck(&mut self, commands: Vec<BraincrapCommand>) -> String {
+    /// Transpiles already lowered instructions into any of the `TranspilerArguments` targets.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BraincrapError::UnsupportedInstruction` if the target cannot express one of the
+    /// instructions.
+    pub fn transpile_instructions(
+        &self,
+        program: &[Spanned<Instruction>],
+        args: &TranspilerArguments,
+    ) -> Result<String, BraincrapError> {
         let mut output = String::new();
+        match args {
+            TranspilerArguments::Brainfuck => self.transpile_brainfuck(program, &mut output)?,
+            TranspilerArguments::C => self.transpile_c(program, &mut output),
+            TranspilerArguments::Rust => self.transpile_rust(program, &mut output),
+            TranspilerArguments::Wat => self.transpile_wat(program, &mut output),
+            TranspilerArguments::Asm => self.transpile_asm(program, &mut 0, &mut output),
+            TranspilerArguments::Llvm => self.transpile_llvm(program, &mut 0, &mut output),
+            TranspilerArguments::Js => self.transpile_js(program, &mut output),
+            TranspilerArguments::Python => self.transpile_python(program, 0, &mut output),
+        }
+        Ok(output)
+    }
 
-        for command in commands {
-            let bf_command = self.transpile_command_bf(&command);
-            output.push_str(&bf_command);
+    /// The shortest addition changing a cell by `delta`, going around the other way if the
+    /// cells wrap.
+    ///
+    /// Without wrapping, `delta` must lead from one value a cell can hold to another, so every
+    /// step in between stays in range.
+    fn bf_delta(&self, delta: i64) -> i64 {
+        if self.tape.overflow != Overflow::Wrap {
+            return delta;
+        }
+        let size = 1i64 << self.tape.cell_bits;
+        let forward = delta.rem_euclid(size);
+        if forward <= size / 2 {
+            forward
+        } else {
+            forward - size
         }
+    }
 
-        if !are_braces_balanced(&output) {
-            error!("Braces not balanced!")
+    /// The value closest to `value` that writes `byte` when a cell holding `value` is changed
+    /// to it.
+    ///
+    /// Output only keeps the lowest byte of a cell, so the closest value on either side that
+    /// ends in `byte` is taken, as long as it does not overflow a cell that does not wrap.
+    fn bf_byte(&self, value: i64, byte: u8) -> i64 {
+        let above = value + (i64::from(byte) - value).rem_euclid(256);
+        let below = above - 256;
+        let fits = |target: i64| {
+            self.tape.overflow == Overflow::Wrap
+                || (self.tape.min()..=self.tape.max()).contains(&target)
+        };
+        if !fits(below) || (fits(above) && above - value <= value - below) {
+            above
+        } else {
+            below
         }
+    }
 
-        output
+    /// Transpiles to Brainfuck
+    ///
+    /// The Brainfuck pointer stays on the last cell it touched, it only catches up with the
+    /// program's pointer when that moves, before loops and at the end.
+    fn transpile_brainfuck(
+        &self,
+        program: &[Spanned<Instruction>],
+        output: &mut String,
+    ) -> Result<(), BraincrapError> {
+        // Where the Brainfuck pointer is relative to the program's pointer
+        let mut cursor = 0;
+        let mut index = 0;
+        while index < program.len() {
+            match &program[index].node {
+                Instruction::Add { offset, delta } => {
+                    output.push_str(&bf_move(offset - cursor));
+                    output.push_str(&bf_add(*delta));
+                    cursor = *offset;
+                }
+                Instruction::Move(distance) => {
+                    output.push_str(&bf_move(distance - cursor));
+                    cursor = 0;
+                }
+                Instruction::SetZero { offset } => {
+                    output.push_str(&bf_move(offset - cursor));
+                    output.push_str("[-]");
+                    cursor = *offset;
+                }
+                Instruction::MulAdd { src, .. } => {
+                    // A multiplication only exists as a loop that clears its source, so the
+                    // `MulAdd`s of one source and the `SetZero` after them become a loop again
+                    let span = program[index].span;
+                    let mut body = String::from("-");
+                    let mut position = *src;
+                    while let Some(Instruction::MulAdd {
+                        src: s,
+                        dst,
+                        factor,
+                    }) = program.get(index).map(|i| &i.node)
+                    {
+                        if s != src {
+                            break;
+                        }
+                        body.push_str(&bf_move(dst - position));
+                        body.push_str(&bf_add(*factor));
+                        position = *dst;
+                        index += 1;
+                    }
+                    if program.get(index).map(|i| &i.node)
+                        != Some(&Instruction::SetZero { offset: *src })
+                    {
+                        return Err(BraincrapError::UnsupportedInstruction {
+                            message: format!(
+                                "Brainfuck can only multiply cell {src} if it is cleared afterwards"
+                            ),
+                            span,
+                        });
+                    }
+                    body.push_str(&bf_move(src - position));
+                    output.push_str(&bf_move(src - cursor));
+                    output.push_str(&format!("[{body}]"));
+                    cursor = *src;
+                }
+                Instruction::Scan { step } => {
+                    output.push_str(&bf_move(-cursor));
+                    output.push_str(&format!("[{}]", bf_move(*step)));
+                    cursor = 0;
+                }
+                Instruction::Loop { body } => {
+                    output.push_str(&bf_move(-cursor));
+                    output.push('[');
+                    self.transpile_brainfuck(body, output)?;
+                    output.push(']');
+                    cursor = 0;
+                }
+                Instruction::Output { offset, count } => {
+                    output.push_str(&bf_move(offset - cursor));
+                    output.push_str(&".".repeat(*count));
+                    cursor = *offset;
+                }
+                Instruction::Input { offset, count } => {
+                    output.push_str(&bf_move(offset - cursor));
+                    output.push_str(&",".repeat(*count));
+                    cursor = *offset;
+                }
+                Instruction::Print { bytes } => {
+                    // The current cell is still 0, build every byte in it and clear it again
+                    output.push_str(&bf_move(-cursor));
+                    cursor = 0;
+                    let mut value = 0;
+                    for &byte in bytes {
+                        let target = self.bf_byte(value, byte);
+                        output.push_str(&bf_add(target - value));
+                        output.push('.');
+                        value = self.tape.wrap(i128::from(target));
+                    }
+                    output.push_str(&bf_add(self.bf_delta(-value)));
+                }
+                Instruction::Init { cells } => {
+                    // The tape is still all 0, so adding the values stores them
+                    for (offset, &value) in cells.iter().enumerate() {
+                        if value != 0 {
+                            output.push_str(&bf_move(offset as isize - cursor));
+                            output.push_str(&bf_add(self.bf_delta(value)));
+                            cursor = offset as isize;
+                        }
+                    }
+                }
+            }
+            index += 1;
+        }
+        output.push_str(&bf_move(-cursor));
+        Ok(())
     }
 
     /// Transpiles to C
-    fn transpile_c(&mut self, commands: Vec<BraincrapCommand>) -> String {
-        let mut output = String::new();
+    fn transpile_c(&self, program: &[Spanned<Instruction>], output: &mut String) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    output.push_str(&self.c_add(*offset, i128::from(*delta)));
+                }
+                Instruction::Move(distance) => output.push_str(&c_move(*distance)),
+                Instruction::SetZero { offset } => {
+                    output.push_str(&format!("({} = 0);", c_cell(*offset)));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    output.push_str(&self.c_mul_add(*src, *dst, *factor));
+                }
+                Instruction::Scan { step } => output.push_str(&self.c_scan(*step)),
+                Instruction::Loop { body } => {
+                    output.push_str("while(*ptr != 0){");
+                    self.transpile_c(body, output);
+                    output.push('}');
+                }
+                Instruction::Output { offset, count } => {
+                    let put = format!("putchar({});", c_cell(*offset));
+                    if *count >= 3 {
+                        output.push_str(&format!("for(int i=0;i<{count};i++){{{put}}}"));
+                    } else {
+                        output.push_str(&put.repeat(*count));
+                    }
+                }
+                Instruction::Input { offset, count } => {
+                    if *count >= 2 {
+                        output.push_str(&format!(
+                            "for(int i=0;i<{count};i++){{{}}}",
+                            self.c_input(*offset)
+                        ));
+                    } else {
+                        output.push_str(&self.c_input(*offset).repeat(*count));
+                    }
+                }
+                Instruction::Print { bytes } => output.push_str(&format!(
+                    "fwrite(\"{}\", 1, {}, stdout);",
+                    c_string(bytes),
+                    bytes.len()
+                )),
+                Instruction::Init { cells } => {
+                    let values: Vec<String> = cells.iter().map(i64::to_string).collect();
+                    output.push_str(&format!(
+                        "{{static const {} init[] = {{{}}}; memcpy(ptr, init, sizeof init);}}",
+                        self.tape.c_type(),
+                        values.join(", ")
+                    ));
+                }
+            }
+        }
+    }
 
-        for command in commands {
-            let c_line = self.transpile_command_c(&command);
-            output.push_str(&c_line);
-            // output.push('\n');
-        }
-
-        output
-    }
-
-    /// Transpiles a single Braincrap command into Brainfuck
-    fn transpile_command_bf(&mut self, command: &BraincrapCommand) -> String {
-        match command {
-            BraincrapCommand::Addition(count) => "+".repeat(*count).to_string(),
-            BraincrapCommand::Substraction(count) => "-".repeat(*count).to_string(),
-            BraincrapCommand::MoveLeft(count) => "<".repeat(*count).to_string(),
-            BraincrapCommand::MoveRight(count) => ">".repeat(*count).to_string(),
-            BraincrapCommand::OpenLoop => "[".to_string(),
-            BraincrapCommand::CloseLoop => "]".to_string(),
-            BraincrapCommand::Output(count) => ".".repeat(*count).to_string(),
-            BraincrapCommand::Input(count) => ",".repeat(*count).to_string(),
-            BraincrapCommand::DefineMacro { name, code, .. } => {
-                let expanded_code = self.transpile_brainfuck(code.clone());
-                self.macros.insert(*name, expanded_code);
-                String::new()
-            }
-            BraincrapCommand::RunMacro { name } => {
-                self.macros.get(name).cloned().unwrap_or_else(String::new)
-            }
-            BraincrapCommand::Import { code, .. } => self.transpile_brainfuck(code.clone()),
-        }
-    }
-
-    /// Transpiles a single Braincrap command into C
-    fn transpile_command_c(&mut self, command: &BraincrapCommand) -> String {
-        match command {
-            BraincrapCommand::Addition(count) => format!("(*ptr += {count});"),
-            BraincrapCommand::Substraction(count) => format!("(*ptr -= {count});"),
-            BraincrapCommand::MoveLeft(count) => format!("(ptr -= {count});"),
-            BraincrapCommand::MoveRight(count) => format!("(ptr += {count});"),
-            BraincrapCommand::OpenLoop => "while(*ptr != 0){".to_string(),
-            BraincrapCommand::CloseLoop => "}".to_string(),
-            BraincrapCommand::Output(count) => {
-                if *count >= 3 {
-                    format!("for(int i=0;i<{count};i++){{putchar(*ptr);}}")
-                } else {
-                    "putchar(*ptr);".repeat(*count).to_string()
+    /// Transpiles to Python, one statement per line indented by `depth` levels
+    fn transpile_python(
+        &self,
+        program: &[Spanned<Instruction>],
+        depth: usize,
+        output: &mut String,
+    ) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    let cell = python_cell(*offset);
+                    let sum = if *delta < 0 {
+                        format!("{cell} - {}", delta.unsigned_abs())
+                    } else {
+                        format!("{cell} + {delta}")
+                    };
+                    python_line(
+                        output,
+                        depth,
+                        &format!("{cell} = {}", self.python_value(&sum)),
+                    );
+                }
+                Instruction::Move(distance) => python_move(output, depth, *distance),
+                Instruction::SetZero { offset } => {
+                    python_line(output, depth, &format!("{} = 0", python_cell(*offset)));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    python_line(output, depth, &self.python_mul_add(*src, *dst, *factor));
+                }
+                Instruction::Scan { step: 1 } => {
+                    python_line(output, depth, "ptr = scan_right(tape, ptr)");
+                }
+                Instruction::Scan { step: -1 } if self.is_byte_tape() => {
+                    python_line(output, depth, "ptr = scan_left(tape, ptr)");
+                }
+                Instruction::Scan { step } => {
+                    python_line(output, depth, "while tape[ptr]:");
+                    python_move(output, depth + 1, *step);
+                }
+                Instruction::Loop { body } => {
+                    python_line(output, depth, "while tape[ptr]:");
+                    if body.is_empty() {
+                        python_line(output, depth, "    pass");
+                    }
+                    self.transpile_python(body, depth + 1, output);
+                }
+                Instruction::Output { offset, count } => {
+                    let byte = if self.is_byte_tape() {
+                        python_cell(*offset)
+                    } else {
+                        format!("{} & 255", python_cell(*offset))
+                    };
+                    let repeat = if *count > 1 {
+                        format!(" * {count}")
+                    } else {
+                        String::new()
+                    };
+                    python_line(
+                        output,
+                        depth,
+                        &format!("out.write(bytes(({byte},)){repeat})"),
+                    );
+                }
+                Instruction::Input { offset, count } => {
+                    for _ in 0..*count {
+                        for statement in self.python_input(*offset) {
+                            python_line(output, depth, &statement);
+                        }
+                    }
+                }
+                // Rust and Python byte strings share their escapes
+                Instruction::Print { bytes } => {
+                    python_line(
+                        output,
+                        depth,
+                        &format!("out.write(b\"{}\")", rust_string(bytes)),
+                    );
+                }
+                Instruction::Init { cells } => {
+                    let values: Vec<String> = cells.iter().map(i64::to_string).collect();
+                    python_line(
+                        output,
+                        depth,
+                        &format!("tape[ptr:ptr + {}] = [{}]", cells.len(), values.join(", ")),
+                    );
                 }
             }
-            BraincrapCommand::Input(count) => {
-                if *count >= 2 {
-                    format!("for(i=0;i<{count};i++){{*ptr = getchar());}}")
-                } else {
-                    "(*ptr = getchar());".repeat(*count).to_string()
+        }
+    }
+
+    /// Transpiles to JavaScript
+    fn transpile_js(&self, program: &[Spanned<Instruction>], output: &mut String) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    output.push_str(&self.js_add(*offset, *delta));
+                }
+                Instruction::Move(distance) => output.push_str(&js_move(*distance)),
+                Instruction::SetZero { offset } => {
+                    output.push_str(&format!("{} = 0;", js_cell(*offset)));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    output.push_str(&self.js_mul_add(*src, *dst, *factor));
+                }
+                // Searching gives -1 instead of leaving the tape when there is no zero
+                Instruction::Scan { step } => output.push_str(&match step {
+                    1 | -1 => format!(
+                        "ptr = tape.{}(0, ptr);\
+                         if (ptr < 0) throw new RangeError(\"Pointer left the tape\");",
+                        if *step == 1 { "indexOf" } else { "lastIndexOf" }
+                    ),
+                    _ => format!("while (tape[ptr]) {{{}}}", js_move(*step)),
+                }),
+                Instruction::Loop { body } => {
+                    // Cells off the tape read as `undefined`, which also ends the loop
+                    output.push_str("while (tape[ptr]) {");
+                    self.transpile_js(body, output);
+                    output.push('}');
+                }
+                Instruction::Output { offset, count } => {
+                    // `Uint8Array.from` keeps the lowest byte of every cell
+                    let put = format!("output.push({});", js_cell(*offset));
+                    if *count >= 3 {
+                        output.push_str(&format!("for (let i = 0; i < {count}; i++) {{{put}}}"));
+                    } else {
+                        output.push_str(&put.repeat(*count));
+                    }
+                }
+                Instruction::Input { offset, count } => {
+                    if *count >= 2 {
+                        output.push_str(&format!(
+                            "for (let i = 0; i < {count}; i++) {{{}}}",
+                            self.js_input(*offset)
+                        ));
+                    } else {
+                        output.push_str(&self.js_input(*offset).repeat(*count));
+                    }
+                }
+                // Calls take at most 65535 arguments, so the bytes are pushed one at a time
+                Instruction::Print { bytes } => {
+                    let values: Vec<String> = bytes.iter().map(u8::to_string).collect();
+                    output.push_str(&format!(
+                        "for (const byte of [{}]) output.push(byte);",
+                        values.join(", ")
+                    ));
+                }
+                Instruction::Init { cells } => {
+                    let values: Vec<String> = cells.iter().map(i64::to_string).collect();
+                    output.push_str(&format!("tape.set([{}], ptr);", values.join(", ")));
+                }
+            }
+        }
+    }
+
+    /// Transpiles to LLVM IR
+    ///
+    /// `names` counts the values and blocks named so far, every loop takes three blocks: one
+    /// testing the current cell, its body and the one after it.
+    fn transpile_llvm(
+        &self,
+        program: &[Spanned<Instruction>],
+        names: &mut usize,
+        output: &mut String,
+    ) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    self.llvm_add(*offset, *delta, names, output);
+                }
+                Instruction::Move(distance) => self.llvm_move(*distance, names, output),
+                Instruction::SetZero { offset } => {
+                    let cell = self.llvm_cell(*offset, names, output);
+                    output.push_str(&format!("  store {} 0, ptr {cell}\n", self.llvm_type()));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    self.llvm_mul_add(*src, *dst, *factor, names, output);
+                }
+                Instruction::Scan { .. } | Instruction::Loop { .. } => {
+                    let label = *names;
+                    *names += 1;
+                    let (test, body, end) = (
+                        format!("loop{label}"),
+                        format!("body{label}"),
+                        format!("end{label}"),
+                    );
+                    output.push_str(&format!("  br label %{test}\n{test}:\n"));
+                    self.llvm_condition(&body, &end, names, output);
+                    output.push_str(&format!("{body}:\n"));
+                    match &instruction.node {
+                        Instruction::Scan { step } => self.llvm_move(*step, names, output),
+                        Instruction::Loop { body } => self.transpile_llvm(body, names, output),
+                        _ => unreachable!(),
+                    }
+                    output.push_str(&format!("  br label %{test}\n{end}:\n"));
+                }
+                Instruction::Output { offset, count } => {
+                    let cell = self.llvm_cell(*offset, names, output);
+                    let value = self.llvm_load(&cell, names, output);
+                    let value = self.llvm_widen(&value, "i32", names, output);
+                    for _ in 0..*count {
+                        let result = llvm_name(names);
+                        output.push_str(&format!("  {result} = call i32 @putchar(i32 {value})\n"));
+                    }
+                }
+                Instruction::Input { offset, count } => {
+                    for _ in 0..*count {
+                        self.llvm_input(*offset, names, output);
+                    }
+                }
+                Instruction::Print { bytes } => {
+                    for byte in bytes {
+                        let result = llvm_name(names);
+                        output.push_str(&format!("  {result} = call i32 @putchar(i32 {byte})\n"));
+                    }
+                }
+                Instruction::Init { cells } => {
+                    for (offset, &value) in cells.iter().enumerate() {
+                        if value != 0 {
+                            let cell = self.llvm_cell(offset as isize, names, output);
+                            output.push_str(&format!(
+                                "  store {} {value}, ptr {cell}\n",
+                                self.llvm_type()
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Transpiles to x86-64 assembly in Intel syntax
+    ///
+    /// `labels` counts the labels used so far, loops and printed strings each take a number.
+    fn transpile_asm(
+        &self,
+        program: &[Spanned<Instruction>],
+        labels: &mut usize,
+        output: &mut String,
+    ) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    output.push_str(&self.asm_add(*offset, *delta));
+                }
+                Instruction::Move(distance) => output.push_str(&self.asm_move(*distance)),
+                Instruction::SetZero { offset } => {
+                    output.push_str(&format!("\tmov {}, 0\n", self.asm_cell(*offset)));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    output.push_str(&self.asm_mul_add(*src, *dst, *factor));
+                }
+                Instruction::Scan { step } => {
+                    let label = *labels;
+                    *labels += 1;
+                    output.push_str(&format!(
+                        ".Lloop{label}:\n\tcmp {}, 0\n\tje .Lend{label}\n{}\tjmp .Lloop{label}\n.Lend{label}:\n",
+                        self.asm_cell(0),
+                        self.asm_move(*step)
+                    ));
+                }
+                Instruction::Loop { body } => {
+                    let label = *labels;
+                    *labels += 1;
+                    output.push_str(&format!(
+                        ".Lloop{label}:\n\tcmp {}, 0\n\tje .Lend{label}\n",
+                        self.asm_cell(0)
+                    ));
+                    self.transpile_asm(body, labels, output);
+                    output.push_str(&format!("\tjmp .Lloop{label}\n.Lend{label}:\n"));
+                }
+                Instruction::Output { offset, count } => {
+                    let put = format!("\tlea rdi, [{}]\n\tcall put\n", self.asm_address(*offset));
+                    output.push_str(&put.repeat(*count));
+                }
+                Instruction::Input { offset, count } => {
+                    output.push_str(&self.asm_input(*offset).repeat(*count));
+                }
+                Instruction::Print { bytes } => {
+                    let label = *labels;
+                    *labels += 1;
+                    // GNU as understands the same escapes as C
+                    output.push_str(&format!(
+                        "\t.section .rodata\n.Lprint{label}:\n\t.ascii \"{}\"\n\t.text\n\
+                         \tmov edi, 1\n\tlea rsi, [rip + .Lprint{label}]\n\tmov edx, {}\n\
+                         \tmov eax, 1\n\tsyscall\n",
+                        c_string(bytes),
+                        bytes.len()
+                    ));
+                }
+                Instruction::Init { cells } => {
+                    for (offset, &value) in cells.iter().enumerate() {
+                        if value != 0 {
+                            output.push_str(&format!(
+                                "\tmov {}, {value}\n",
+                                self.asm_cell(offset as isize)
+                            ));
+                        }
+                    }
                 }
             }
-            BraincrapCommand::DefineMacro { name, code, .. } => {
-                let expanded_code = self.transpile_c(code.clone());
-                self.macros.insert(*name, expanded_code);
-                String::new()
+        }
+    }
+
+    /// Transpiles moving the pointer by `distance` cells into assembly.
+    fn asm_move(&self, distance: isize) -> String {
+        let bytes = distance * (self.tape.cell_bits / 8) as isize;
+        if bytes < 0 {
+            format!("\tsub rbx, {}\n", -bytes)
+        } else {
+            format!("\tadd rbx, {bytes}\n")
+        }
+    }
+
+    /// Transpiles to WAT, one instruction per line
+    ///
+    /// A loop is a `block` around a `loop`, so `br_if 1` leaves it and `br 0` repeats it.
+    fn transpile_wat(&self, program: &[Spanned<Instruction>], output: &mut String) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    output.push_str(&self.wat_add(*offset, *delta));
+                }
+                Instruction::Move(distance) => output.push_str(&self.wat_move(*distance)),
+                Instruction::SetZero { offset } => {
+                    output.push_str(&self.wat_store(*offset, "(i32.const 0)"));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    output.push_str(&self.wat_mul_add(*src, *dst, *factor));
+                }
+                Instruction::Scan { step } => {
+                    output.push_str(&format!(
+                        "(block\n  (loop\n    (br_if 1 (i32.eqz {}))\n    {}\n    (br 0)))",
+                        self.wat_load(0),
+                        self.wat_move(*step)
+                    ));
+                }
+                Instruction::Loop { body } => {
+                    let mut inner = String::new();
+                    self.transpile_wat(body, &mut inner);
+                    output.push_str(&format!(
+                        "(block\n  (loop\n    (br_if 1 (i32.eqz {}))\n",
+                        self.wat_load(0)
+                    ));
+                    for line in inner.lines() {
+                        output.push_str(&format!("    {line}\n"));
+                    }
+                    output.push_str("    (br 0)))");
+                }
+                Instruction::Output { offset, count } => {
+                    let put = format!("(call $write {})", self.wat_load(*offset));
+                    output.push_str(&vec![put; *count].join("\n"));
+                }
+                Instruction::Input { offset, count } => {
+                    output.push_str(&vec![self.wat_input(*offset); *count].join("\n"));
+                }
+                Instruction::Print { bytes } => {
+                    let writes: Vec<String> = bytes
+                        .iter()
+                        .map(|byte| format!("(call $write (i32.const {byte}))"))
+                        .collect();
+                    output.push_str(&writes.join("\n"));
+                }
+                Instruction::Init { cells } => {
+                    let stores: Vec<String> = cells
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &value)| value != 0)
+                        .map(|(offset, value)| {
+                            self.wat_store(offset as isize, &format!("(i32.const {value})"))
+                        })
+                        .collect();
+                    output.push_str(&stores.join("\n"));
+                }
             }
-            BraincrapCommand::RunMacro { name } => {
-                self.macros.get(name).cloned().unwrap_or_else(String::new)
+            output.push('\n');
+        }
+    }
+
+    /// Transpiles to Rust
+    fn transpile_rust(&self, program: &[Spanned<Instruction>], output: &mut String) {
+        for instruction in program {
+            match &instruction.node {
+                Instruction::Add { offset, delta } => {
+                    output.push_str(&self.rust_add(*offset, *delta));
+                }
+                Instruction::Move(distance) => output.push_str(&rust_move(*distance)),
+                Instruction::SetZero { offset } => {
+                    output.push_str(&format!("{} = 0;", rust_cell(*offset)));
+                }
+                Instruction::MulAdd { src, dst, factor } => {
+                    output.push_str(&self.rust_mul_add(*src, *dst, *factor));
+                }
+                Instruction::Scan { step } => output.push_str(&Self::rust_scan(*step)),
+                Instruction::Loop { body } => {
+                    output.push_str("while tape[ptr] != 0 {");
+                    self.transpile_rust(body, output);
+                    output.push('}');
+                }
+                Instruction::Output { offset, count } => {
+                    let put = format!("out.write_all(&[{} as u8]).unwrap();", rust_cell(*offset));
+                    if *count >= 3 {
+                        output.push_str(&format!("for _ in 0..{count} {{{put}}}"));
+                    } else {
+                        output.push_str(&put.repeat(*count));
+                    }
+                }
+                Instruction::Input { offset, count } => {
+                    if *count >= 2 {
+                        output.push_str(&format!(
+                            "for _ in 0..{count} {{{}}}",
+                            self.rust_input(*offset)
+                        ));
+                    } else {
+                        output.push_str(&self.rust_input(*offset).repeat(*count));
+                    }
+                }
+                Instruction::Print { bytes } => output.push_str(&format!(
+                    "out.write_all(b\"{}\").unwrap();",
+                    rust_string(bytes)
+                )),
+                Instruction::Init { cells } => {
+                    let values: Vec<String> = cells.iter().map(i64::to_string).collect();
+                    output.push_str(&format!(
+                        "tape[ptr..ptr + {}].copy_from_slice(&[{}]);",
+                        cells.len(),
+                        values.join(", ")
+                    ));
+                }
             }
-            BraincrapCommand::Import { code, .. } => self.transpile_c(code.clone()),
         }
     }
 }
+
+/// Adds `delta` to the current cell in Brainfuck.
+fn bf_add(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    sign.repeat(delta.unsigned_abs() as usize)
+}
+
+/// Moves the pointer by `distance` cells in Brainfuck.
+fn bf_move(distance: isize) -> String {
+    let direction = if distance < 0 { "<" } else { ">" };
+    direction.repeat(distance.unsigned_abs())
+}
+
+/// Moves the pointer by `distance` cells in C.
+fn c_move(distance: isize) -> String {
+    if distance < 0 {
+        format!("(ptr -= {});", -distance)
+    } else {
+        format!("(ptr += {distance});")
+    }
+}
+
+/// The C expression for the cell at `offset`.
+fn c_cell(offset: isize) -> String {
+    if offset == 0 {
+        "*ptr".to_string()
+    } else {
+        format!("ptr[{offset}]")
+    }
+}
+
+/// Escapes bytes for a C string literal.
+fn c_string(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b' '..=b'~' => escaped.push(char::from(byte)),
+            // Octal escapes stop after three digits, unlike hexadecimal ones
+            _ => escaped.push_str(&format!("\\{byte:03o}")),
+        }
+    }
+    escaped
+}
+
+/// The C pointer to the cell at `offset`.
+fn c_address(offset: isize) -> String {
+    match offset {
+        0 => "ptr".to_string(),
+        _ if offset < 0 => format!("ptr - {}", -offset),
+        _ => format!("ptr + {offset}"),
+    }
+}
+
+/// Moves the pointer by `distance` cells in Rust.
+fn rust_move(distance: isize) -> String {
+    if distance < 0 {
+        format!("ptr -= {};", -distance)
+    } else {
+        format!("ptr += {distance};")
+    }
+}
+
+/// The Rust expression for the cell at `offset`, which is bounds checked.
+fn rust_cell(offset: isize) -> String {
+    match offset {
+        0 => "tape[ptr]".to_string(),
+        _ if offset < 0 => format!("tape[ptr - {}]", -offset),
+        _ => format!("tape[ptr + {offset}]"),
+    }
+}
+
+/// Escapes bytes for a Rust byte string literal.
+fn rust_string(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b' '..=b'~' => escaped.push(char::from(byte)),
+            _ => escaped.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    escaped
+}
+
+/// Takes the next free LLVM value name.
+fn llvm_name(names: &mut usize) -> String {
+    *names += 1;
+    format!("%t{}", *names - 1)
+}
+
+/// Moves the pointer by `distance` cells in JavaScript.
+fn js_move(distance: isize) -> String {
+    if distance < 0 {
+        format!("ptr -= {};", -distance)
+    } else {
+        format!("ptr += {distance};")
+    }
+}
+
+/// The JavaScript index of the cell at `offset`.
+fn js_index(offset: isize) -> String {
+    match offset {
+        0 => "ptr".to_string(),
+        _ if offset < 0 => format!("ptr - {}", -offset),
+        _ => format!("ptr + {offset}"),
+    }
+}
+
+/// The JavaScript expression for the cell at `offset`.
+fn js_cell(offset: isize) -> String {
+    format!("tape[{}]", js_index(offset))
+}
+
+/// Writes the Python moving the pointer by `distance` cells.
+///
+/// Python wraps negative indices around to the end of the tape, so moving left is checked.
+fn python_move(output: &mut String, depth: usize, distance: isize) {
+    if distance < 0 {
+        python_line(output, depth, &format!("ptr -= {}", -distance));
+        python_line(
+            output,
+            depth,
+            "if ptr < 0: raise IndexError(\"Pointer left the tape\")",
+        );
+    } else {
+        python_line(output, depth, &format!("ptr += {distance}"));
+    }
+}
+
+/// The Python expression for the cell at `offset`.
+fn python_cell(offset: isize) -> String {
+    match offset {
+        0 => "tape[ptr]".to_string(),
+        _ if offset < 0 => format!("tape[ptr - {}]", -offset),
+        _ => format!("tape[ptr + {offset}]"),
+    }
+}
+
+/// Writes a line of Python indented by `depth` levels.
+fn python_line(output: &mut String, depth: usize, line: &str) {
+    output.push_str(&"    ".repeat(depth));
+    output.push_str(line);
+    output.push('\n');
+}
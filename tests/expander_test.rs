@@ -0,0 +1,269 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::{BraincrapError, BraincrapWarning};
+use braincrap_rs::expander::Expander;
+use braincrap_rs::parser::{BraincrapCommand, Parser};
+use braincrap_rs::span::Spanned;
+use braincrap_rs::tokenizer::Lexer;
+use std::path::PathBuf;
+
+fn expand(source: &str) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+    let tokens = Lexer::new(source.to_string()).tokenize()?;
+    let mut parser = Parser::new(&tokens, PathBuf::from("tests/fixtures"));
+    parser.set_include_dirs(vec![PathBuf::from("tests/fixtures/lib")]);
+    let commands = parser.parse()?;
+    Expander::with_imports(parser.into_imports()).expand(commands)
+}
+
+#[test]
+fn test_expand_macro_arguments() {
+    let commands = expand("#move(n, step=+) >%n%step\n@move(2)@move(-1, step=.)@move(0)").unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::MoveRight(2),
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::MoveLeft(1),
+            BraincrapCommand::Output(1),
+            BraincrapCommand::Addition(1),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_forwarded_arguments() {
+    for source in [
+        "#inner(k) +%k\n#outer(n) @inner(%n)\n@outer(3)",
+        "#inner(k) +%k\n#outer(n) {\n    @inner(%n)\n}\n@outer(3)",
+        "#inner(k) %k\n#outer(n) @inner({+%n})\n@outer(3)",
+        "#inner(k=-) +%k\n#outer(n) @inner(k=%n)\n@outer(3)",
+    ] {
+        let commands = expand(source).unwrap();
+
+        assert_eq!(
+            commands,
+            vec![BraincrapCommand::Addition(3)],
+            "{source:?} gave {commands:?}"
+        );
+    }
+}
+
+#[test]
+fn test_expand_imports_in_macros_with_parameters() {
+    // The body is parsed again at the call, with the imports of the file that defines it
+    let commands = expand("#m(x) {\n    $inc.bf\n    $library.bf\n    L%x\n}\n@m(-)@m(-)").unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::Addition(2),
+            BraincrapCommand::Substraction(1),
+            BraincrapCommand::Addition(2),
+            BraincrapCommand::Substraction(1),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_argument_spans() {
+    let result = expand("#move(n) >%n\n@move( x)");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::InvalidMacroArgument { span, .. }) if (span.line, span.column) == (2, 8)
+    ));
+
+    let result = expand("#loop(body) [%body\n@loop(-]])");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::UnbalancedBrackets { bracket: ']', span }) if (span.line, span.column) == (2, 9)
+    ));
+}
+
+#[test]
+fn test_expand_forward_references() {
+    let commands = expand("@a b\n#a @b+\n#b >\n#c(n) -%n\n@c(2)").unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::MoveRight(1),
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::MoveRight(1),
+            BraincrapCommand::Substraction(2),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_forward_reference_into_later_import() {
+    let commands = expand("@L\n$lib/library.bf").unwrap();
+
+    assert_eq!(commands, vec![BraincrapCommand::Addition(2)]);
+}
+
+#[test]
+fn test_expand_late_binding() {
+    // `b` sees whichever `a` is defined when `b` runs
+    let commands = expand("#a +\n#b a\nb\n#a -\nb").unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::Substraction(1),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_undefined_macro() {
+    let result = expand("#a @missing\na");
+
+    assert!(matches!(
+        result,
+        Err(BraincrapError::UndefinedMacro { ref name, span }) if name == "missing" && span.column == 4
+    ));
+}
+
+#[test]
+fn test_expand_recursive_macro() {
+    let result = expand("#a @b\n#b {\n    >@c\n}\n#c a\n@a");
+
+    let Err(BraincrapError::RecursiveMacro { chain, span }) = result else {
+        panic!("Expected a recursive macro, got {result:?}");
+    };
+    assert_eq!(chain, vec!["a", "b", "c", "a"]);
+    assert_eq!((span.line, span.column), (5, 4));
+}
+
+#[test]
+fn test_expand_invalid_arguments() {
+    let result = expand("#move(n) >%n\n@move(x)");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::InvalidMacroArgument { .. })
+    ));
+
+    let result = expand("#later(n) >%n\n@early(1, 2)\n#early(x) @later(%x)");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::WrongArgumentCount { found: 2, .. })
+    ));
+
+    let result = expand("#inner(k) +%k\n#outer(n) @inner(%m)");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::InvalidMacroArgument { ref message, .. }) if message == "unknown parameter %m"
+    ));
+
+    for source in [
+        "#mv(n) [->%n+<%n]\n@mv(99999999999)",
+        "#mv(n) [->%n+<%n]\n#w(k) @mv(>%k)\n@w(999999999999)",
+    ] {
+        let result = expand(source);
+        assert!(
+            matches!(result, Err(BraincrapError::InvalidMacroArgument { .. })),
+            "{source:?} gave {result:?}"
+        );
+    }
+
+    let result = expand("#loop(body) [%body\n@loop(-]])");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::UnbalancedBrackets { bracket: ']', .. })
+    ));
+}
+
+#[test]
+fn test_expand_namespaced_import() {
+    let commands = expand("$namespaced.bf as n\n#A <\nn::add_two@n::A A").unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::MoveRight(1),
+            BraincrapCommand::MoveLeft(1),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_namespaced_macros_are_hidden() {
+    for source in [
+        "$namespaced.bf as n\nadd_two",
+        "$namespaced.bf as n\nn::_inc",
+    ] {
+        let result = expand(source);
+
+        assert!(
+            matches!(result, Err(BraincrapError::UndefinedMacro { .. })),
+            "{source:?} gave {result:?}"
+        );
+    }
+}
+
+#[test]
+fn test_expand_private_macros() {
+    // Private macros are used by the file's own macros but are not visible to the importer
+    let commands = expand("$namespaced.bf\n@add_two").unwrap();
+    assert_eq!(
+        commands,
+        vec![BraincrapCommand::Addition(1), BraincrapCommand::Addition(1)]
+    );
+
+    let result = expand("$namespaced.bf\n@_inc");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::UndefinedMacro { ref name, .. }) if name == "_inc"
+    ));
+}
+
+#[test]
+fn test_expand_private_macros_of_the_caller_are_hidden() {
+    let result = expand("$uses_main.bf\n#_secret ---\n@uses_main");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::UndefinedMacro { ref name, .. }) if name == "_secret"
+    ));
+
+    let commands = expand("$uses_main.bf\n#shared ---\n@uses_public").unwrap();
+    assert_eq!(commands, vec![BraincrapCommand::Substraction(3)]);
+}
+
+#[test]
+fn test_expand_block_argument_from_caller() {
+    let commands = expand("$namespaced.bf as n\n#dec -\n@n::apply({@dec})").unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::MoveRight(1),
+            BraincrapCommand::Substraction(1),
+            BraincrapCommand::MoveLeft(1),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_shadowing_warning() {
+    let tokens = Lexer::new("$namespaced.bf\n#B +\n#A -\n#B -".to_string())
+        .tokenize()
+        .unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from("tests/fixtures"))
+        .parse()
+        .unwrap();
+    let mut expander = Expander::new();
+    expander.expand(commands).unwrap();
+
+    let shadowed: Vec<(&str, usize)> = expander
+        .warnings()
+        .iter()
+        .map(|warning| match warning {
+            BraincrapWarning::ShadowedMacro { name, span, .. } => (name.as_str(), span.line),
+        })
+        .collect();
+    assert_eq!(shadowed, vec![("A", 3), ("B", 4)]);
+}
@@ -0,0 +1 @@
+$cycle_b.bf
@@ -0,0 +1,2 @@
++
+$cycle_a.bf
@@ -0,0 +1 @@
+#L ++
@@ -0,0 +1,5 @@
+; A public macro built from a private helper
+#_inc +
+#add_two @_inc@_inc
+#apply(body) > %body <
+#A >
@@ -0,0 +1,2 @@
+$inc.bf
+$./inc.bf
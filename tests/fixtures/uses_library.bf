@@ -0,0 +1,2 @@
+$library.bf
+L
@@ -0,0 +1,3 @@
+; Calls macros it does not define, which only the caller can provide
+#uses_main @_secret
+#uses_public @shared
@@ -0,0 +1,283 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::interpreter::Interpreter;
+use braincrap_rs::parser::{BraincrapCommand, Parser};
+use braincrap_rs::span::Spanned;
+use braincrap_rs::tape::{EofBehavior, Overflow, TapeConfig};
+use braincrap_rs::tokenizer::Lexer;
+use std::cell::RefCell;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+fn spanned<T>(items: Vec<T>) -> Vec<Spanned<T>> {
+    items.into_iter().map(Spanned::from).collect()
+}
+
+fn run(source: &str, input: &[u8]) -> Vec<u8> {
+    let mut lexer = Lexer::new(source.to_string());
+    let tokens = lexer.tokenize().unwrap();
+    let mut parser = Parser::new(&tokens, PathBuf::from("examples"));
+    let commands = parser.parse().unwrap();
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(input, &mut output);
+    interpreter.run(commands).unwrap();
+    output
+}
+
+#[test]
+fn test_run_basic_commands() {
+    let commands = spanned(vec![
+        BraincrapCommand::Addition(3),
+        BraincrapCommand::MoveRight(2),
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(1),
+    ]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+    interpreter.run(commands).unwrap();
+
+    assert_eq!(&interpreter.tape()[..3], &[3, 0, 255]);
+    assert_eq!(interpreter.pointer(), 1);
+}
+
+#[test]
+fn test_run_hello_world() {
+    let source = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    assert_eq!(run(source, b""), b"Hello World!\n");
+}
+
+#[test]
+fn test_run_twice_on_the_same_tape() {
+    let commands = |source: &str| {
+        let tokens = Lexer::new(source.to_string()).tokenize().unwrap();
+        Parser::new(&tokens, PathBuf::from(".")).parse().unwrap()
+    };
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+    interpreter.run(commands("+++")).unwrap();
+    interpreter.run(commands("[->++<]")).unwrap();
+    assert_eq!(&interpreter.tape()[..2], &[0, 6]);
+
+    interpreter.run(commands(">[-]")).unwrap();
+    assert_eq!(&interpreter.tape()[..2], &[0, 0]);
+    assert_eq!(interpreter.pointer(), 1);
+
+    interpreter.run(commands("++.<[-]")).unwrap();
+    assert_eq!(&interpreter.tape()[..2], &[0, 2]);
+    drop(interpreter);
+    assert_eq!(output, vec![2]);
+}
+
+#[test]
+fn test_run_input_and_eof() {
+    assert_eq!(run(",.,.,.", b"ab"), vec![b'a', b'b', 255]);
+}
+
+#[test]
+fn test_run_macros() {
+    assert_eq!(run("#a +++\n#b aa.\nb", b""), vec![6]);
+}
+
+#[test]
+fn test_run_multi_character_macros() {
+    let source = "#a +\n#ab ++\n#add_three @ab@a\n@add_three.a.@ab.";
+
+    assert_eq!(run(source, b""), vec![3, 4, 6]);
+}
+
+#[test]
+fn test_run_parameterised_macros() {
+    let source = "#move(n) >%n\n\
+        #copy(src, dst, tmp) >%src[->%dst+>%tmp+<%tmp<%dst<%src]>%tmp[-<%tmp>%src+<%src>%tmp]<%tmp\n\
+        #repeat(body, times=3) %body%body%body\n\
+        @repeat({+++})@copy(0, 2, tmp=3)@move(2).<<@repeat(@move(1)+)<.";
+
+    assert_eq!(run(source, b""), vec![9, 10]);
+}
+
+#[test]
+fn test_run_multi_line_macros() {
+    let source =
+        "#letter {\n    ; 8 * 8 + 1 is 'A'\n    ++++++++[>++++++++<-]>+\n\n    .\n}\n@letter.";
+
+    assert_eq!(run(source, b""), b"AA");
+}
+
+#[test]
+fn test_run_import() {
+    // 6 * 7 through the `M` macro of examples/std.bf
+    let source = "$std.bf\n++++++>+++++++<M.";
+
+    assert_eq!(run(source, b""), vec![42]);
+}
+
+/// Writes into a shared buffer, so a reader can see what was written before it.
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads `A` and records what had been written when it was asked for it.
+struct PromptedInput {
+    output: Rc<RefCell<Vec<u8>>>,
+    prompts: Vec<Vec<u8>>,
+}
+
+impl Read for PromptedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.prompts.push(self.output.borrow().clone());
+        buf[0] = b'A';
+        Ok(1)
+    }
+}
+
+#[test]
+fn test_run_flushes_output_before_input() {
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let mut input = PromptedInput {
+        output: Rc::clone(&written),
+        prompts: Vec::new(),
+    };
+    let output = BufWriter::new(SharedOutput(Rc::clone(&written)));
+    let tokens = Lexer::new("++++++[->++++++++<]>.,.,".to_string())
+        .tokenize()
+        .unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from(".")).parse().unwrap();
+
+    Interpreter::new(&mut input, output).run(commands).unwrap();
+
+    assert_eq!(input.prompts, vec![b"0".to_vec(), b"0A".to_vec()]);
+}
+
+#[test]
+fn test_run_eof_behaviors() {
+    for (eof, expected) in [
+        (EofBehavior::Zero, 0),
+        (EofBehavior::MinusOne, 255),
+        (EofBehavior::Unchanged, 7),
+    ] {
+        let config = TapeConfig {
+            eof,
+            ..TapeConfig::default()
+        };
+        let commands = spanned(vec![
+            BraincrapCommand::Addition(7),
+            BraincrapCommand::Input(1),
+        ]);
+
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_config(&[][..], &mut output, config);
+        interpreter.run(commands).unwrap();
+
+        assert_eq!(interpreter.tape()[0], expected);
+    }
+}
+
+#[test]
+fn test_run_wide_signed_cells() {
+    let config = TapeConfig {
+        cell_bits: 16,
+        signed: true,
+        tape_length: 4,
+        ..TapeConfig::default()
+    };
+    let commands = spanned(vec![
+        BraincrapCommand::Addition(300),
+        BraincrapCommand::MoveRight(1),
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::Input(1),
+    ]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_config(&[][..], &mut output, config);
+    interpreter.run(commands).unwrap();
+
+    assert_eq!(interpreter.tape(), &[300, -1, 0, 0]);
+}
+
+#[test]
+fn test_run_overflow_error() {
+    let config = TapeConfig {
+        overflow: Overflow::Error,
+        ..TapeConfig::default()
+    };
+    let commands = spanned(vec![BraincrapCommand::Substraction(1)]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_config(&[][..], &mut output, config);
+
+    assert!(matches!(
+        interpreter.run(commands),
+        Err(BraincrapError::CellOverflow { pointer: 0, .. })
+    ));
+}
+
+#[test]
+fn test_run_tape_length() {
+    let config = TapeConfig {
+        tape_length: 2,
+        ..TapeConfig::default()
+    };
+    let commands = spanned(vec![BraincrapCommand::MoveRight(2)]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_config(&[][..], &mut output, config);
+
+    assert!(matches!(
+        interpreter.run(commands),
+        Err(BraincrapError::PointerOutOfBounds { pointer: 2, .. })
+    ));
+}
+
+#[test]
+fn test_run_unbalanced_loops() {
+    let commands = spanned(vec![BraincrapCommand::CloseLoop]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+
+    assert!(matches!(
+        interpreter.run(commands),
+        Err(BraincrapError::UnbalancedBrackets { .. })
+    ));
+}
+
+#[test]
+fn test_run_pointer_underflow() {
+    let commands = spanned(vec![BraincrapCommand::MoveLeft(1)]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+
+    assert!(matches!(
+        interpreter.run(commands),
+        Err(BraincrapError::PointerOutOfBounds { pointer: -1, .. })
+    ));
+}
+
+#[test]
+fn test_run_undefined_macro() {
+    let commands = spanned(vec![BraincrapCommand::RunMacro {
+        name: "z".to_string(),
+    }]);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+
+    assert!(matches!(
+        interpreter.run(commands),
+        Err(BraincrapError::UndefinedMacro { name, .. }) if name == "z"
+    ));
+}
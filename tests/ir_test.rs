@@ -0,0 +1,494 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::interpreter::Interpreter;
+use braincrap_rs::ir::{dump, lower, Instruction};
+use braincrap_rs::parser::BraincrapCommand;
+use braincrap_rs::parser::Parser;
+use braincrap_rs::span::Spanned;
+use braincrap_rs::tape::{EofBehavior, Overflow, TapeConfig};
+use braincrap_rs::tokenizer::Lexer;
+use braincrap_rs::transpiler::{Transpiler, TranspilerArguments};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::{env, fs};
+
+fn spanned<T>(items: Vec<T>) -> Vec<Spanned<T>> {
+    items.into_iter().map(Spanned::from).collect()
+}
+
+/// `ptr[1] = ptr[0] * 3`, then `ptr[1]` is printed, read into `ptr[-1]` and scanned back to 0.
+fn multiply() -> Vec<Spanned<Instruction>> {
+    spanned(vec![
+        Instruction::Move(1),
+        Instruction::Add {
+            offset: 0,
+            delta: 4,
+        },
+        Instruction::MulAdd {
+            src: 0,
+            dst: 1,
+            factor: 3,
+        },
+        Instruction::SetZero { offset: 0 },
+        Instruction::Output {
+            offset: 1,
+            count: 1,
+        },
+        Instruction::Input {
+            offset: -1,
+            count: 1,
+        },
+        Instruction::Scan { step: -1 },
+    ])
+}
+
+#[test]
+fn test_lower_loops_into_a_tree() {
+    let commands = spanned(vec![
+        BraincrapCommand::Addition(2),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::MoveRight(3),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::MoveLeft(1),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(2),
+        BraincrapCommand::Input(1),
+    ]);
+
+    let expected = spanned(vec![
+        Instruction::Add {
+            offset: 0,
+            delta: 2,
+        },
+        Instruction::Loop {
+            body: spanned(vec![
+                Instruction::Add {
+                    offset: 0,
+                    delta: -1,
+                },
+                Instruction::Loop {
+                    body: spanned(vec![Instruction::Move(3)]),
+                },
+                Instruction::Move(-1),
+            ]),
+        },
+        Instruction::Output {
+            offset: 0,
+            count: 2,
+        },
+        Instruction::Input {
+            offset: 0,
+            count: 1,
+        },
+    ]);
+
+    assert_eq!(lower(&commands).unwrap(), expected);
+}
+
+#[test]
+fn test_lower_unbalanced_loops() {
+    let open = spanned(vec![BraincrapCommand::OpenLoop]);
+    let close = spanned(vec![
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::CloseLoop,
+    ]);
+
+    assert!(matches!(
+        lower(&open),
+        Err(BraincrapError::UnbalancedBrackets { bracket: '[', .. })
+    ));
+    assert!(matches!(
+        lower(&close),
+        Err(BraincrapError::UnbalancedBrackets { bracket: ']', .. })
+    ));
+}
+
+#[test]
+fn test_transpile_instructions() {
+    let transpiler = Transpiler::new();
+
+    assert_eq!(
+        transpiler
+            .transpile_instructions(&multiply(), &TranspilerArguments::Brainfuck)
+            .unwrap(),
+        ">++++[->+++<]>.<<,>[<]"
+    );
+    assert_eq!(
+        transpiler
+            .transpile_instructions(&multiply(), &TranspilerArguments::C)
+            .unwrap(),
+        "(ptr += 1);(*ptr += 4);(ptr[1] += (uint32_t)*ptr * 3u);(*ptr = 0);putchar(ptr[1]);\
+         (ptr[-1] = getchar());(ptr = scan_left(tape, ptr));"
+    );
+    assert_eq!(
+        transpiler
+            .transpile_instructions(&multiply(), &TranspilerArguments::Rust)
+            .unwrap(),
+        "ptr += 1;tape[ptr] = tape[ptr].wrapping_add(4);\
+         tape[ptr + 1] = tape[ptr + 1].wrapping_add(tape[ptr].wrapping_mul(3));tape[ptr] = 0;\
+         out.write_all(&[tape[ptr + 1] as u8]).unwrap();\
+         tape[ptr - 1] = read(&mut out).map_or(255, |b| b as u8);\
+         ptr = tape[..=ptr].iter().rposition(|&c| c == 0).expect(\"Pointer left the tape\");"
+    );
+    assert!(transpiler
+        .transpile_instructions(&multiply(), &TranspilerArguments::Js)
+        .unwrap()
+        .ends_with(
+            "ptr = tape.lastIndexOf(0, ptr);\
+             if (ptr < 0) throw new RangeError(\"Pointer left the tape\");"
+        ));
+}
+
+#[test]
+fn test_transpile_multiplication_keeping_its_source_to_brainfuck() {
+    let transpiler = Transpiler::new();
+    let mut program = multiply();
+    program.remove(3);
+
+    assert!(matches!(
+        transpiler.transpile_instructions(&program, &TranspilerArguments::Brainfuck),
+        Err(BraincrapError::UnsupportedInstruction { .. })
+    ));
+    let nested = vec![Spanned::from(Instruction::Loop { body: program })];
+    assert!(matches!(
+        transpiler.transpile_instructions(&nested, &TranspilerArguments::Brainfuck),
+        Err(BraincrapError::UnsupportedInstruction { .. })
+    ));
+}
+
+#[test]
+fn test_execute_instructions() {
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&b"\x07"[..], &mut output);
+    interpreter.execute(&multiply()).unwrap();
+
+    assert_eq!(&interpreter.tape()[..3], &[7, 0, 12]);
+    assert_eq!(interpreter.pointer(), 1);
+    assert_eq!(output, vec![12]);
+}
+
+#[test]
+fn test_dump() {
+    assert_eq!(
+        dump(&multiply()),
+        "move 1\n[0] += 4\n[1] += [0] * 3\n[0] = 0\noutput [1] x1\ninput [-1] x1\nscan -1\n"
+    );
+
+    let tokens = Lexer::new("+\n[-<]".to_string()).tokenize().unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from(".")).parse().unwrap();
+    let expected = "\
+[0] += 1                         ; 1:1
+loop                             ; 2:1
+    [0] -= 1                     ; 2:2
+    move -1                      ; 2:3
+end
+";
+    assert_eq!(dump(&lower(&commands).unwrap()), expected);
+}
+
+#[test]
+fn test_transpile_evaluated_prefix() {
+    let program = vec![
+        Spanned::from(Instruction::Print {
+            bytes: b"a\"\\\n\x01".to_vec(),
+        }),
+        Spanned::from(Instruction::Init {
+            cells: vec![0, 3, 255],
+        }),
+    ];
+    let transpiler = Transpiler::new();
+
+    assert_eq!(
+        transpiler
+            .transpile_instructions(&program, &TranspilerArguments::C)
+            .unwrap(),
+        "fwrite(\"a\\\"\\\\\\n\\001\", 1, 5, stdout);\
+         {static const uint8_t init[] = {0, 3, 255}; memcpy(ptr, init, sizeof init);}"
+    );
+    assert_eq!(
+        transpiler
+            .transpile_instructions(&program, &TranspilerArguments::Rust)
+            .unwrap(),
+        "out.write_all(b\"a\\\"\\\\\\n\\x01\").unwrap();\
+         tape[ptr..ptr + 3].copy_from_slice(&[0, 3, 255]);"
+    );
+    assert_eq!(
+        transpiler
+            .transpile_instructions(&program, &TranspilerArguments::Js)
+            .unwrap(),
+        "for (const byte of [97, 34, 92, 10, 1]) output.push(byte);tape.set([0, 3, 255], ptr);"
+    );
+    let bf = transpiler
+        .transpile_instructions(&program, &TranspilerArguments::Brainfuck)
+        .unwrap();
+    assert!(bf.starts_with(&format!("{}.{}.", "+".repeat(97), "-".repeat(63))));
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+    interpreter.execute(&program).unwrap();
+    assert_eq!(&interpreter.tape()[..4], &[0, 3, 255, 0]);
+    assert_eq!(output, b"a\"\\\n\x01");
+}
+
+#[test]
+fn test_transpile_evaluated_prefix_to_brainfuck_on_any_tape() {
+    let wide = Transpiler::with_config(TapeConfig {
+        cell_bits: 32,
+        ..TapeConfig::default()
+    });
+    let program = spanned(vec![
+        Instruction::Print { bytes: vec![255] },
+        Instruction::Init {
+            cells: vec![4294967295],
+        },
+    ]);
+    assert_eq!(
+        wide.transpile_instructions(&program, &TranspilerArguments::Brainfuck)
+            .unwrap(),
+        "-.+-"
+    );
+
+    let program = spanned(vec![Instruction::Print {
+        bytes: b"\xc8a\xff".to_vec(),
+    }]);
+    for (signed, overflow) in [
+        (true, Overflow::Error),
+        (false, Overflow::Error),
+        (true, Overflow::Saturate),
+    ] {
+        let config = TapeConfig {
+            signed,
+            overflow,
+            ..TapeConfig::default()
+        };
+        let bf = Transpiler::with_config(config)
+            .transpile_instructions(&program, &TranspilerArguments::Brainfuck)
+            .unwrap();
+        let tokens = Lexer::new(bf).tokenize().unwrap();
+        let commands = Parser::new(&tokens, PathBuf::from(".")).parse().unwrap();
+
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_config(&[][..], &mut output, config);
+        interpreter.run(commands).unwrap();
+        assert_eq!(interpreter.tape()[0], 0);
+        assert_eq!(output, b"\xc8a\xff");
+    }
+}
+
+/// Prints `ok\n` before `multiply()`, so a run given the input 7 writes `ok\n\x0c`.
+fn greeting() -> Vec<Spanned<Instruction>> {
+    let mut program = spanned(vec![Instruction::Print {
+        bytes: b"ok\n".to_vec(),
+    }]);
+    program.extend(multiply());
+    program
+}
+
+/// Writes `source` to `file` in a temporary directory, runs `commands` in order and checks that
+/// the last one writes `ok\n\x0c` when given the input 7.
+///
+/// The commands are built from the path `source` is written to. The test is skipped, with a
+/// message, when one of them runs a tool that is not installed.
+fn run_program(file: &str, source: &str, commands: impl FnOnce(&Path) -> Vec<Command>) {
+    let dir = env::temp_dir().join(format!(
+        "braincrap_{}_{}",
+        file.replace('.', "_"),
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(file);
+    fs::write(&path, source).unwrap();
+
+    let mut commands = commands(&path);
+    let mut run = commands.pop().unwrap();
+    let missing = |command: &Command| {
+        eprintln!(
+            "Skipping {file}, {} is not installed",
+            command.get_program().to_string_lossy()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    };
+    for mut command in commands {
+        match command.status() {
+            Ok(status) => assert!(status.success(), "{command:?} failed"),
+            Err(e) if e.kind() == ErrorKind::NotFound => return missing(&command),
+            Err(e) => panic!("{command:?} could not run: {e}"),
+        }
+    }
+    let child = run.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => return missing(&run),
+        Err(e) => panic!("{run:?} could not run: {e}"),
+    };
+    child.stdin.take().unwrap().write_all(b"\x07").unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ok\n\x0c");
+}
+
+#[test]
+fn test_run_rust_program() {
+    let transpiler = Transpiler::new();
+    let code = transpiler
+        .transpile_instructions(&greeting(), &TranspilerArguments::Rust)
+        .unwrap();
+
+    run_program("main.rs", &transpiler.rust_program(&code), |path| {
+        let mut rustc = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()));
+        rustc.arg(path).arg("-o").arg(path.with_extension(""));
+        vec![rustc, Command::new(path.with_extension(""))]
+    });
+}
+
+#[test]
+fn test_validate_wat_program() {
+    let mut program = spanned(vec![
+        Instruction::Print {
+            bytes: b"ok\n".to_vec(),
+        },
+        Instruction::Init {
+            cells: vec![0, 3, -1],
+        },
+    ]);
+    program.extend(multiply());
+    program.push(Spanned::from(Instruction::Loop {
+        body: spanned(vec![
+            Instruction::Scan { step: 2 },
+            Instruction::Input {
+                offset: 1,
+                count: 2,
+            },
+        ]),
+    }));
+
+    for (cell_bits, signed, overflow, eof) in [
+        (8, false, Overflow::Wrap, EofBehavior::MinusOne),
+        (16, true, Overflow::Saturate, EofBehavior::Zero),
+        (32, false, Overflow::Error, EofBehavior::Unchanged),
+    ] {
+        let transpiler = Transpiler::with_config(TapeConfig {
+            cell_bits,
+            signed,
+            overflow,
+            eof,
+            ..TapeConfig::default()
+        });
+        let code = transpiler
+            .transpile_instructions(&program, &TranspilerArguments::Wat)
+            .unwrap();
+        let module = wat::parse_str(transpiler.wat_program(&code)).unwrap();
+        wasmparser::validate(&module).unwrap();
+    }
+}
+
+#[test]
+fn test_run_asm_program() {
+    let transpiler = Transpiler::with_config(TapeConfig {
+        cell_bits: 16,
+        overflow: Overflow::Saturate,
+        ..TapeConfig::default()
+    });
+    let code = transpiler
+        .transpile_instructions(&greeting(), &TranspilerArguments::Asm)
+        .unwrap();
+
+    run_program("main.s", &transpiler.asm_program(&code), |path| {
+        let mut assembler = Command::new("as");
+        assembler.arg(path).arg("-o").arg(path.with_extension("o"));
+        let mut linker = Command::new("ld");
+        linker
+            .arg(path.with_extension("o"))
+            .arg("-o")
+            .arg(path.with_extension(""));
+        vec![assembler, linker, Command::new(path.with_extension(""))]
+    });
+}
+
+/// The major version of the installed `lli`, such as 14 for "Ubuntu LLVM version 14.0.6".
+fn lli_major_version() -> Option<u32> {
+    let output = Command::new("lli").arg("--version").output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let version = output.split("version ").nth(1)?;
+    version.split(['.', ' ', '\n']).next()?.parse().ok()
+}
+
+#[test]
+fn test_run_llvm_program() {
+    // Opaque pointers are only read by default since LLVM 15, and behind a flag since LLVM 13
+    let flag = match lli_major_version() {
+        Some(version) if version < 13 => {
+            eprintln!("Skipping main.ll, lli {version} cannot read opaque pointers");
+            return;
+        }
+        Some(13) => Some("-force-opaque-pointers"),
+        Some(14) => Some("-opaque-pointers"),
+        _ => None,
+    };
+    for (signed, overflow, eof) in [
+        (false, Overflow::Wrap, EofBehavior::MinusOne),
+        (true, Overflow::Saturate, EofBehavior::Zero),
+        (false, Overflow::Error, EofBehavior::Unchanged),
+    ] {
+        let transpiler = Transpiler::with_config(TapeConfig {
+            signed,
+            overflow,
+            eof,
+            ..TapeConfig::default()
+        });
+        let code = transpiler
+            .transpile_instructions(&greeting(), &TranspilerArguments::Llvm)
+            .unwrap();
+
+        run_program("main.ll", &transpiler.llvm_program(&code), |path| {
+            let mut lli = Command::new("lli");
+            lli.args(flag).arg(path);
+            vec![lli]
+        });
+    }
+}
+
+#[test]
+fn test_run_node_program() {
+    let transpiler = Transpiler::new();
+    let code = transpiler
+        .transpile_instructions(&greeting(), &TranspilerArguments::Js)
+        .unwrap();
+
+    run_program("main.js", &transpiler.node_program(&code), |path| {
+        let mut node = Command::new("node");
+        node.arg(path);
+        vec![node]
+    });
+}
+
+#[test]
+fn test_run_python_program() {
+    for (cell_bits, signed, overflow) in [
+        (8, false, Overflow::Wrap),
+        (16, true, Overflow::Saturate),
+        (32, false, Overflow::Error),
+    ] {
+        let transpiler = Transpiler::with_config(TapeConfig {
+            cell_bits,
+            signed,
+            overflow,
+            ..TapeConfig::default()
+        });
+        let code = transpiler
+            .transpile_instructions(&greeting(), &TranspilerArguments::Python)
+            .unwrap();
+
+        run_program("main.py", &transpiler.python_program(&code), |path| {
+            let mut python = Command::new("python3");
+            python.arg(path);
+            vec![python]
+        });
+    }
+}
@@ -0,0 +1,572 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::expander::Expander;
+use braincrap_rs::interpreter::Interpreter;
+use braincrap_rs::ir::{lower, Instruction};
+use braincrap_rs::optimizer::{
+    evaluate_prefix, fold_offsets, optimize, peephole, simplify_loops, simplify_scans, Pass,
+    PassManager,
+};
+use braincrap_rs::parser::Parser;
+use braincrap_rs::span::Spanned;
+use braincrap_rs::tape::{Overflow, TapeConfig};
+use braincrap_rs::tokenizer::Lexer;
+use braincrap_rs::transpiler::{Transpiler, TranspilerArguments};
+use std::path::PathBuf;
+
+fn lowered(source: &str) -> Vec<Spanned<Instruction>> {
+    let tokens = Lexer::new(source.to_string()).tokenize().unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from("examples"))
+        .parse()
+        .unwrap();
+    lower(&Expander::new().expand(commands).unwrap()).unwrap()
+}
+
+/// Runs a program and returns the start of the tape, the pointer and the output, or the error
+/// it stopped with.
+fn execute(
+    program: &[Spanned<Instruction>],
+    config: TapeConfig,
+    input: &[u8],
+) -> Result<(Vec<i64>, usize, Vec<u8>), String> {
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_config(input, &mut output, config);
+    interpreter.execute(program).map_err(|e| e.to_string())?;
+    let tape = interpreter.tape()[..8].to_vec();
+    let pointer = interpreter.pointer();
+    Ok((tape, pointer, output))
+}
+
+/// Checks that optimizing `source` changes nothing observable for every input byte.
+fn assert_equivalent(source: &str, config: TapeConfig) {
+    // Fill the surrounding cells so additions to them are visible
+    let source = format!("+>++>,>+++<{source}<<.>.>.>.");
+    let program = lowered(&source);
+    let optimized = optimize(program.clone(), &config);
+    assert_ne!(program, optimized, "{source} was not optimized");
+
+    for byte in 0..=255 {
+        assert_eq!(
+            execute(&program, config, &[byte]),
+            execute(&optimized, config, &[byte]),
+            "{source} with input {byte} and {config:?}"
+        );
+    }
+}
+
+#[test]
+fn test_peephole_across_macros() {
+    let program = peephole(lowered("#a ++>\n#b <-\nab>>"), &TapeConfig::default());
+
+    let expected = vec![
+        Spanned::from(Instruction::Add {
+            offset: 0,
+            delta: 1,
+        }),
+        Spanned::from(Instruction::Move(2)),
+    ];
+    assert_eq!(program, expected);
+    assert_eq!(
+        peephole(lowered(",>+-<"), &TapeConfig::default()),
+        lowered(",")
+    );
+}
+
+#[test]
+fn test_peephole_keeps_checked_additions() {
+    let saturate = TapeConfig {
+        overflow: Overflow::Saturate,
+        ..TapeConfig::default()
+    };
+    let program = peephole(lowered("#a +\n#b -\n,aab"), &saturate);
+
+    let expected = vec![
+        Spanned::from(Instruction::Input {
+            offset: 0,
+            count: 1,
+        }),
+        Spanned::from(Instruction::Add {
+            offset: 0,
+            delta: 2,
+        }),
+        Spanned::from(Instruction::Add {
+            offset: 0,
+            delta: -1,
+        }),
+    ];
+    assert_eq!(program, expected);
+}
+
+#[test]
+fn test_peephole_removes_dead_loops() {
+    let config = TapeConfig::default();
+
+    // Nothing was written yet, and a loop only ends on a 0
+    assert_eq!(peephole(lowered(">>[-]<[>.<]"), &config), lowered(">"));
+    assert_eq!(peephole(lowered(",[>][-][.]"), &config), lowered(",[>]"));
+    assert_eq!(
+        peephole(lowered(",[[-]>][-]."), &config),
+        lowered(",[[-]>].")
+    );
+    // The pointer may have moved onto a cell that is not 0
+    assert_eq!(peephole(lowered("+>[-]"), &config), lowered("+>[-]"));
+    assert_eq!(peephole(lowered(",[-]>[-]"), &config), lowered(",[-]>[-]"));
+}
+
+#[test]
+fn test_peephole_is_equivalent() {
+    for source in ["+-", "<>+-<+>", "[-][+]", "[->+<]>[<+>-]<[-]", "<+>->+<"] {
+        assert_equivalent(source, TapeConfig::default());
+    }
+}
+
+#[test]
+fn test_simplify_clear_loop() {
+    let expected = vec![Spanned::from(Instruction::SetZero { offset: 0 })];
+
+    assert_eq!(
+        simplify_loops(lowered("[-]"), &TapeConfig::default()),
+        expected
+    );
+    assert_eq!(
+        simplify_loops(lowered("[+]"), &TapeConfig::default()),
+        expected
+    );
+}
+
+#[test]
+fn test_simplify_multiply_loop() {
+    let expected = vec![
+        Spanned::from(Instruction::MulAdd {
+            src: 0,
+            dst: 1,
+            factor: 3,
+        }),
+        Spanned::from(Instruction::MulAdd {
+            src: 0,
+            dst: -1,
+            factor: -2,
+        }),
+        Spanned::from(Instruction::SetZero { offset: 0 }),
+    ];
+
+    assert_eq!(
+        simplify_loops(lowered("[->+++<<-->]"), &TapeConfig::default()),
+        expected
+    );
+    assert_eq!(
+        simplify_loops(lowered("[>---<<++>+]"), &TapeConfig::default()),
+        expected
+    );
+}
+
+#[test]
+fn test_simplify_nested_loops() {
+    let program = simplify_loops(lowered("[>[-]<-]"), &TapeConfig::default());
+
+    let expected = vec![Spanned::from(Instruction::Loop {
+        body: vec![
+            Spanned::from(Instruction::Move(1)),
+            Spanned::from(Instruction::SetZero { offset: 0 }),
+            Spanned::from(Instruction::Move(-1)),
+            Spanned::from(Instruction::Add {
+                offset: 0,
+                delta: -1,
+            }),
+        ],
+    })];
+    assert_eq!(program, expected);
+}
+
+#[test]
+fn test_simplify_keeps_other_loops() {
+    let wrap = TapeConfig::default();
+    let saturate = TapeConfig {
+        overflow: Overflow::Saturate,
+        ..TapeConfig::default()
+    };
+    let signed = TapeConfig {
+        signed: true,
+        overflow: Overflow::Error,
+        ..TapeConfig::default()
+    };
+
+    for source in ["[]", "[>]", "[->+<<]", "[--]", "[-.]", "[-,]"] {
+        assert_eq!(simplify_loops(lowered(source), &wrap), lowered(source));
+    }
+    // Counting up or adding to a cell twice may saturate or fail half way through
+    assert_eq!(simplify_loops(lowered("[+]"), &saturate), lowered("[+]"));
+    assert_eq!(
+        simplify_loops(lowered("[->+>-<+<]"), &saturate),
+        lowered("[->+>-<+<]")
+    );
+    // Signed cells may be negative, and never count down to 0 without wrapping
+    assert_eq!(simplify_loops(lowered("[-]"), &signed), lowered("[-]"));
+}
+
+#[test]
+fn test_simplified_loops_are_equivalent() {
+    let configs = [
+        TapeConfig::default(),
+        TapeConfig {
+            signed: true,
+            ..TapeConfig::default()
+        },
+        TapeConfig {
+            cell_bits: 16,
+            overflow: Overflow::Saturate,
+            ..TapeConfig::default()
+        },
+        TapeConfig {
+            overflow: Overflow::Error,
+            ..TapeConfig::default()
+        },
+    ];
+
+    for config in configs {
+        for source in ["[-]", "[->+<]", "[->>+<<]", "[<+>-]", "[-<--->>+<]"] {
+            assert_equivalent(source, config);
+        }
+    }
+    for config in &configs[..2] {
+        assert_equivalent("[+]", *config);
+        assert_equivalent("[+<+++>>-<]", *config);
+        assert_equivalent("[>+>+<<-]>>[<<+>>-]<<", *config);
+        assert_equivalent("[-<+>-<->+]", *config);
+    }
+}
+
+#[test]
+fn test_simplify_scan_loops() {
+    let program = simplify_scans(lowered("[>][<<<][>[<]]"));
+
+    let expected = vec![
+        Spanned::from(Instruction::Scan { step: 1 }),
+        Spanned::from(Instruction::Scan { step: -3 }),
+        Spanned::from(Instruction::Loop {
+            body: vec![
+                Spanned::from(Instruction::Move(1)),
+                Spanned::from(Instruction::Scan { step: -1 }),
+            ],
+        }),
+    ];
+    assert_eq!(program, expected);
+    assert_eq!(simplify_scans(lowered("[><]")), lowered("[><]"));
+}
+
+#[test]
+fn test_simplified_scans_are_equivalent() {
+    for source in ["[>]", "[<]", "[>>]", "[<<]", "<<[>>>]", "[-[>]<]"] {
+        assert_equivalent(source, TapeConfig::default());
+    }
+}
+
+#[test]
+fn test_scan_off_the_tape() {
+    let config = TapeConfig {
+        tape_length: 5,
+        ..TapeConfig::default()
+    };
+    let program = optimize(lowered("+>+>+>+>+<<<[>>]"), &config);
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_config(&[][..], &mut output, config);
+    assert!(matches!(
+        interpreter.execute(&program),
+        Err(BraincrapError::PointerOutOfBounds { pointer: 5, .. })
+    ));
+}
+
+#[test]
+fn test_fold_offsets() {
+    let program = fold_offsets(lowered(">+>+<<-"));
+
+    let expected = vec![
+        Spanned::from(Instruction::Add {
+            offset: 1,
+            delta: 1,
+        }),
+        Spanned::from(Instruction::Add {
+            offset: 2,
+            delta: 1,
+        }),
+        Spanned::from(Instruction::Add {
+            offset: 0,
+            delta: -1,
+        }),
+    ];
+    assert_eq!(program, expected);
+}
+
+#[test]
+fn test_fold_offsets_moves_before_loops_and_io() {
+    let program = fold_offsets(lowered(">+>[>+<-<]>.<<"));
+
+    let expected = vec![
+        Spanned::from(Instruction::Add {
+            offset: 1,
+            delta: 1,
+        }),
+        Spanned::from(Instruction::Move(2)),
+        Spanned::from(Instruction::Loop {
+            body: vec![
+                Spanned::from(Instruction::Add {
+                    offset: 1,
+                    delta: 1,
+                }),
+                Spanned::from(Instruction::Add {
+                    offset: 0,
+                    delta: -1,
+                }),
+                Spanned::from(Instruction::Move(-1)),
+            ],
+        }),
+        Spanned::from(Instruction::Move(1)),
+        Spanned::from(Instruction::Output {
+            offset: 0,
+            count: 1,
+        }),
+        Spanned::from(Instruction::Move(-2)),
+    ];
+    assert_eq!(program, expected);
+}
+
+#[test]
+fn test_folded_offsets_are_equivalent() {
+    for source in [
+        ">+>+<<-",
+        "<<+>>>-<",
+        "[>-<-]>[<+>-]<",
+        ">>[-]<<[->>+<<]",
+        ">[<]>.<,>",
+    ] {
+        assert_equivalent(source, TapeConfig::default());
+    }
+}
+
+#[test]
+fn test_transpile_cancelled_commands() {
+    for (source, c_expected, bf_expected) in [
+        (
+            "+-<>[].,",
+            "while(*ptr != 0){}putchar(*ptr);(*ptr = getchar());",
+            "[].,",
+        ),
+        // The second definition of `a` cancels the first one out
+        ("#a +\na\n#a -\na", "", ""),
+    ] {
+        let tokens = Lexer::new(source.to_string()).tokenize().unwrap();
+        let commands = Parser::new(&tokens, PathBuf::from("examples"))
+            .parse()
+            .unwrap();
+
+        let c_result = Transpiler::new()
+            .transpile(commands.clone(), &TranspilerArguments::C)
+            .unwrap();
+        let bf_result = Transpiler::new()
+            .transpile(commands, &TranspilerArguments::Brainfuck)
+            .unwrap();
+
+        assert_eq!(c_result, c_expected, "{source:?}");
+        assert_eq!(bf_result, bf_expected, "{source:?}");
+    }
+}
+
+#[test]
+fn test_transpile_folded_offsets() {
+    let tokens = Lexer::new(">+>+<<-.".to_string()).tokenize().unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from("examples"))
+        .parse()
+        .unwrap();
+
+    let c_result = Transpiler::new()
+        .transpile(commands.clone(), &TranspilerArguments::C)
+        .unwrap();
+    let bf_result = Transpiler::new()
+        .transpile(commands, &TranspilerArguments::Brainfuck)
+        .unwrap();
+
+    assert_eq!(
+        c_result,
+        "(ptr[1] += 1);(ptr[2] += 1);(*ptr -= 1);putchar(*ptr);"
+    );
+    assert_eq!(bf_result, ">+>+<<-.");
+}
+
+#[test]
+fn test_transpile_simplified_loops() {
+    let source = "#0 [-]\n#U [>+>+<<-]>>[<<+>>-]<<\n,U>0";
+    let tokens = Lexer::new(source.to_string()).tokenize().unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from("examples"))
+        .parse()
+        .unwrap();
+
+    let c_result = Transpiler::new()
+        .transpile(commands.clone(), &TranspilerArguments::C)
+        .unwrap();
+    let bf_result = Transpiler::new()
+        .transpile(commands, &TranspilerArguments::Brainfuck)
+        .unwrap();
+
+    assert_eq!(
+        c_result,
+        "(*ptr = getchar());(ptr[1] += *ptr);(ptr[2] += *ptr);(*ptr = 0);(*ptr += ptr[2]);\
+         (ptr[2] = 0);(ptr[1] = 0);(ptr += 1);"
+    );
+    assert_eq!(bf_result, ",[->+>+<<]>>[-<<+>>]<[-]");
+}
+
+#[test]
+fn test_transpile_scans() {
+    let tokens = Lexer::new(",[>],[<],[>>]".to_string()).tokenize().unwrap();
+    let commands = Parser::new(&tokens, PathBuf::from("examples"))
+        .parse()
+        .unwrap();
+    let wide = TapeConfig {
+        cell_bits: 16,
+        ..TapeConfig::default()
+    };
+
+    let c_result = Transpiler::new()
+        .transpile(commands.clone(), &TranspilerArguments::C)
+        .unwrap();
+    let wide_result = Transpiler::with_config(wide)
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
+
+    assert_eq!(
+        c_result,
+        "(*ptr = getchar());(ptr = scan_right(tape, ptr));(*ptr = getchar());\
+         (ptr = scan_left(tape, ptr));(*ptr = getchar());while(*ptr != 0){(ptr += 2);}"
+    );
+    assert_eq!(
+        wide_result,
+        "(*ptr = getchar());while(*ptr != 0){(ptr += 1);}(*ptr = getchar());\
+         while(*ptr != 0){(ptr -= 1);}(*ptr = getchar());while(*ptr != 0){(ptr += 2);}"
+    );
+}
+
+#[test]
+fn test_pass_manager_levels() {
+    let config = TapeConfig::default();
+    let program = lowered(",+-[-]>[<+>-]<");
+
+    assert_eq!(PassManager::with_level(0).passes(), &[]);
+    assert_eq!(
+        PassManager::with_level(0).run(program.clone(), &config),
+        program
+    );
+    assert_eq!(PassManager::with_level(1).passes(), &[Pass::Peephole]);
+    assert_eq!(
+        PassManager::with_level(1).run(program.clone(), &config),
+        lowered(",[-]>[<+>-]<")
+    );
+    assert_eq!(
+        PassManager::with_level(PassManager::MAX_LEVEL).run(program.clone(), &config),
+        optimize(program, &config)
+    );
+    assert_eq!(
+        PassManager::with_level(3).passes().last(),
+        Some(&Pass::EvaluatePrefix {
+            budget: PassManager::DEFAULT_STEP_BUDGET
+        })
+    );
+}
+
+#[test]
+fn test_pass_manager_inspect() {
+    let mut passes = PassManager::new();
+    passes.add(Pass::SimplifyLoops);
+    passes.add(Pass::FoldOffsets);
+
+    let mut seen = Vec::new();
+    let program = passes.run_with(
+        lowered(",>[-]<."),
+        &TapeConfig::default(),
+        |pass, program| seen.push(format!("{pass}: {}", program.len())),
+    );
+
+    assert_eq!(seen, vec!["simplify-loops: 5", "fold-offsets: 3"]);
+    assert_eq!(program.len(), 3);
+}
+
+#[test]
+fn test_evaluate_prefix() {
+    let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++\
+                 .------.--------.>>+.>++.,.";
+    let program = evaluate_prefix(lowered(hello), &TapeConfig::default(), 10_000);
+
+    let expected = vec![
+        Spanned::from(Instruction::Print {
+            bytes: b"Hello World!\n".to_vec(),
+        }),
+        Spanned::from(Instruction::Init {
+            cells: vec![0, 0, 72, 100, 87, 33, 10],
+        }),
+        Spanned::from(Instruction::Move(6)),
+        Spanned::from(Instruction::Input {
+            offset: 0,
+            count: 1,
+        }),
+        Spanned::from(Instruction::Output {
+            offset: 0,
+            count: 1,
+        }),
+    ];
+    assert_eq!(program, expected);
+}
+
+#[test]
+fn test_evaluate_prefix_stops() {
+    let config = TapeConfig::default();
+
+    // Input, leaving the tape and overflowing are left for run time
+    assert_eq!(
+        evaluate_prefix(lowered(",+++"), &config, 100),
+        lowered(",+++")
+    );
+    assert_eq!(evaluate_prefix(lowered("<+"), &config, 100), lowered("<+"));
+    let error = TapeConfig {
+        overflow: Overflow::Error,
+        ..TapeConfig::default()
+    };
+    assert_eq!(
+        evaluate_prefix(lowered("+.-->+"), &error, 100),
+        vec![
+            Spanned::from(Instruction::Print { bytes: vec![1] }),
+            Spanned::from(Instruction::Init { cells: vec![1] }),
+            Spanned::from(Instruction::Add {
+                offset: 0,
+                delta: -2,
+            }),
+            Spanned::from(Instruction::Move(1)),
+            Spanned::from(Instruction::Add {
+                offset: 0,
+                delta: 1,
+            }),
+        ]
+    );
+
+    // A loop that does not finish within the budget is left out completely
+    let program = evaluate_prefix(lowered("++[.>+<]"), &config, 100);
+    assert_eq!(program[0], Instruction::Init { cells: vec![2] });
+    assert_eq!(program[1..], lowered("[.>+<]"));
+}
+
+#[test]
+fn test_evaluated_prefix_is_equivalent() {
+    for source in ["[->+<]>[-<++>]<.", "+[+[>+<-]]>.", "[[-]<->]+++.>[<+>-]"] {
+        assert_equivalent(source, TapeConfig::default());
+    }
+    // Runs into the limit of the step budget
+    let mut passes = PassManager::with_level(3);
+    passes.set_step_budget(20);
+    let program = lowered("++++++[>++++++<-.]>.,.");
+    let evaluated = passes.run(program.clone(), &TapeConfig::default());
+
+    assert!(matches!(evaluated[0].node, Instruction::Init { .. }));
+    assert_eq!(
+        execute(&program, TapeConfig::default(), b"a"),
+        execute(&evaluated, TapeConfig::default(), b"a")
+    );
+}
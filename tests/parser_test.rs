@@ -1,11 +1,22 @@
 #![allow(unexpected_cfgs)]
-use braincrap_rs::parser::{BraincrapCommand, Parser};
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::parser::{BraincrapCommand, MacroParameter, Parser};
+use braincrap_rs::span::{FileId, SourceMap, Span, Spanned};
 use braincrap_rs::tokenizer::BraincrapToken;
+use braincrap_rs::tokenizer::Lexer;
 use std::path::PathBuf;
 
+fn spanned<T>(items: Vec<T>) -> Vec<Spanned<T>> {
+    items.into_iter().map(Spanned::from).collect()
+}
+
+fn canonical(path: &str) -> PathBuf {
+    PathBuf::from(path).canonicalize().unwrap()
+}
+
 #[test]
 fn test_parse_basic_commands() {
-    let tokens = vec![
+    let tokens = spanned(vec![
         BraincrapToken::Plus(1),
         BraincrapToken::Minus(1),
         BraincrapToken::Right(1),
@@ -14,10 +25,10 @@ fn test_parse_basic_commands() {
         BraincrapToken::Comma(1),
         BraincrapToken::LeftBracket,
         BraincrapToken::RightBracket,
-    ];
+    ]);
 
     let mut parser = Parser::new(&tokens, PathBuf::from("."));
-    let commands = parser.parse();
+    let commands = parser.parse().unwrap();
 
     assert_eq!(
         commands,
@@ -36,55 +47,62 @@ fn test_parse_basic_commands() {
 
 #[test]
 fn test_parse_macro_definition() {
-    let tokens = vec![
+    let tokens = spanned(vec![
         BraincrapToken::Hash,
-        BraincrapToken::Char('a'),
+        BraincrapToken::Name("a".to_string()),
         BraincrapToken::String("+".to_string()),
-    ];
+    ]);
 
     let mut parser = Parser::new(&tokens, PathBuf::from("."));
-    let commands = parser.parse();
+    let commands = parser.parse().unwrap();
 
     assert_eq!(
         commands,
         vec![BraincrapCommand::DefineMacro {
-            name: 'a',
-            tokens: vec![BraincrapToken::Plus(1)],
-            code: vec![BraincrapCommand::Addition(1)],
+            name: "a".to_string(),
+            parameters: vec![],
+            tokens: spanned(vec![BraincrapToken::Plus(1)]),
+            code: spanned(vec![BraincrapCommand::Addition(1)]),
         }]
     );
 }
 
 #[test]
 fn test_parse_macro_execution() {
-    let tokens = vec![BraincrapToken::Char('a'), BraincrapToken::Char('b')];
+    let tokens = spanned(vec![BraincrapToken::Char('a'), BraincrapToken::Char('b')]);
 
     let mut parser = Parser::new(&tokens, PathBuf::from("."));
-    let commands = parser.parse();
+    let commands = parser.parse().unwrap();
 
     assert_eq!(
         commands,
         vec![
-            BraincrapCommand::RunMacro { name: 'a' },
-            BraincrapCommand::RunMacro { name: 'b' },
+            BraincrapCommand::RunMacro {
+                name: "a".to_string()
+            },
+            BraincrapCommand::RunMacro {
+                name: "b".to_string()
+            },
         ]
     );
 }
 
 #[test]
 fn test_parse_import() {
-    let tokens = vec![
+    let tokens = spanned(vec![
         BraincrapToken::Dollar,
         BraincrapToken::String("file.bcf".to_string()),
-    ];
+    ]);
 
-    let mut parser = Parser::new(&tokens, PathBuf::from("."));
-    let commands = parser.parse();
+    let mut parser = Parser::new(&tokens, PathBuf::from("tests/fixtures"));
+    let commands = parser.parse().unwrap();
 
     assert_eq!(
         commands,
         vec![BraincrapCommand::Import {
             file: "file.bcf".to_string(),
+            path: canonical("tests/fixtures/file.bcf"),
+            alias: None,
             tokens: vec![],
             code: vec![],
         }]
@@ -93,31 +111,36 @@ fn test_parse_import() {
 
 #[test]
 fn test_parse_mixed_commands_and_macro() {
-    let tokens = vec![
+    let tokens = spanned(vec![
         BraincrapToken::Plus(1),
         BraincrapToken::Hash,
-        BraincrapToken::Char('a'),
+        BraincrapToken::Name("a".to_string()),
         BraincrapToken::String("+".to_string()),
         BraincrapToken::Char('b'),
         BraincrapToken::Dollar,
         BraincrapToken::String("file.bcf".to_string()),
-    ];
+    ]);
 
-    let mut parser = Parser::new(&tokens, PathBuf::from("."));
-    let commands = parser.parse();
+    let mut parser = Parser::new(&tokens, PathBuf::from("tests/fixtures"));
+    let commands = parser.parse().unwrap();
 
     assert_eq!(
         commands,
         vec![
             BraincrapCommand::Addition(1),
             BraincrapCommand::DefineMacro {
-                name: 'a',
-                tokens: vec![BraincrapToken::Plus(1)],
-                code: vec![BraincrapCommand::Addition(1)],
+                name: "a".to_string(),
+                parameters: vec![],
+                tokens: spanned(vec![BraincrapToken::Plus(1)]),
+                code: spanned(vec![BraincrapCommand::Addition(1)]),
+            },
+            BraincrapCommand::RunMacro {
+                name: "b".to_string()
             },
-            BraincrapCommand::RunMacro { name: 'b' },
             BraincrapCommand::Import {
                 file: "file.bcf".to_string(),
+                path: canonical("tests/fixtures/file.bcf"),
+                alias: None,
                 tokens: vec![],
                 code: vec![],
             }
@@ -125,12 +148,265 @@ fn test_parse_mixed_commands_and_macro() {
     );
 }
 
+fn parse_source(source: &str) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+    let tokens = Lexer::new(source.to_string()).tokenize()?;
+    Parser::new(&tokens, PathBuf::from(".")).parse()
+}
+
+#[test]
+fn test_parse_macro_call_with_arguments() {
+    let commands = parse_source("#move(n, step=+) >%n%step\n@move(2)@move(-1, step=.)").unwrap();
+
+    assert_eq!(
+        commands[0],
+        BraincrapCommand::DefineMacro {
+            name: "move".to_string(),
+            parameters: vec![
+                MacroParameter {
+                    name: "n".to_string(),
+                    default: None,
+                },
+                MacroParameter {
+                    name: "step".to_string(),
+                    default: Some("+".to_string()),
+                },
+            ],
+            tokens: spanned(vec![
+                BraincrapToken::Right(1),
+                BraincrapToken::Parameter("n".to_string()),
+                BraincrapToken::Parameter("step".to_string()),
+            ]),
+            code: vec![],
+        }
+    );
+    assert_eq!(
+        commands[1..],
+        vec![
+            BraincrapCommand::CallMacro {
+                name: "move".to_string(),
+                arguments: spanned(vec!["2".to_string()]),
+            },
+            BraincrapCommand::CallMacro {
+                name: "move".to_string(),
+                arguments: spanned(vec!["-1".to_string(), "step=.".to_string()]),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_macro_argument_count() {
+    let result = parse_source("#copy(src, dst, tmp=3) +\n@copy(0)");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::WrongArgumentCount {
+            min: 2,
+            max: 3,
+            found: 1,
+            ..
+        })
+    ));
+
+    let result = parse_source("#a +\n@a(1)");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::WrongArgumentCount {
+            max: 0,
+            found: 1,
+            ..
+        })
+    ));
+
+    let result = parse_source("#move(n) >%n\n@move");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::WrongArgumentCount { found: 0, .. })
+    ));
+}
+
+#[test]
+fn test_parse_invalid_macro_arguments() {
+    for source in [
+        "#move(n) >%n\n@move(m=1)",
+        "#move(n) >%n\n@move(1, n=1)",
+        "#copy(src, dst) +\n@copy(src=1, 2)",
+        "#move(n) >%m",
+        "#move(n, n) >",
+    ] {
+        let result = parse_source(source);
+
+        assert!(
+            matches!(result, Err(BraincrapError::InvalidMacroArgument { .. })),
+            "{source:?} gave {result:?}"
+        );
+    }
+}
+
+#[test]
+fn test_parse_unbalanced_macro_body() {
+    let result = parse_source("#open {\n    +\n    [>\n}\n#close ]\n@open@close");
+    assert!(matches!(
+        result,
+        Err(BraincrapError::UnbalancedBrackets {
+            bracket: '[',
+            span: Span {
+                line: 3,
+                column: 5,
+                ..
+            }
+        })
+    ));
+}
+
 #[test]
 fn test_parse_empty_input() {
-    let tokens: Vec<BraincrapToken> = vec![];
+    let tokens: Vec<Spanned<BraincrapToken>> = vec![];
 
     let mut parser = Parser::new(&tokens, PathBuf::from("."));
-    let commands = parser.parse();
+    let commands = parser.parse().unwrap();
 
     assert_eq!(commands, Vec::<BraincrapCommand>::new());
 }
+
+#[test]
+fn test_parse_missing_import() {
+    let tokens = spanned(vec![
+        BraincrapToken::Dollar,
+        BraincrapToken::String("missing.bcf".to_string()),
+    ]);
+
+    let mut parser = Parser::new(&tokens, PathBuf::from("tests/fixtures"));
+    let result = parser.parse();
+
+    assert!(matches!(result, Err(BraincrapError::ImportNotFound { .. })));
+}
+
+#[test]
+fn test_parse_spans_in_macros_and_imports() {
+    let mut sources = SourceMap::new();
+    let source = "$file.bcf\n#a +>\na".to_string();
+    let file = sources.add(PathBuf::from("main.bf"), source.clone());
+    let tokens = Lexer::with_file(source, file).tokenize().unwrap();
+
+    let mut parser = Parser::with_sources(&tokens, PathBuf::from("tests/fixtures"), sources);
+    let commands = parser.parse().unwrap();
+
+    assert_eq!((commands[0].span.start, commands[0].span.end), (0, 9));
+    let BraincrapCommand::DefineMacro { code, .. } = &commands[1].node else {
+        panic!("Expected a macro definition, got {:?}", commands[1]);
+    };
+    assert_eq!((code[1].span.line, code[1].span.column), (2, 5));
+    assert_eq!((commands[2].span.line, commands[2].span.column), (3, 1));
+
+    let sources = parser.into_sources();
+    assert_eq!(sources.get(file).unwrap().path, PathBuf::from("main.bf"));
+    assert!(sources.get(FileId(1)).is_some());
+}
+
+fn parse_file(
+    path: &str,
+    include_once: bool,
+) -> Result<Vec<Spanned<BraincrapCommand>>, BraincrapError> {
+    let path = PathBuf::from(path);
+    let source = std::fs::read_to_string(&path).unwrap();
+    let tokens = Lexer::new(source).tokenize().unwrap();
+
+    let mut parser = Parser::new(&tokens, path.parent().unwrap().to_path_buf());
+    parser.set_file(&path);
+    parser.set_include_once(include_once);
+    parser.parse()
+}
+
+#[test]
+fn test_parse_import_cycle() {
+    let result = parse_file("tests/fixtures/cycle_a.bf", true);
+
+    let Err(BraincrapError::ImportCycle { chain, .. }) = result else {
+        panic!("Expected an import cycle, got {result:?}");
+    };
+    assert_eq!(
+        chain,
+        vec![
+            PathBuf::from("tests/fixtures/cycle_a.bf"),
+            PathBuf::from("tests/fixtures/cycle_b.bf"),
+            PathBuf::from("tests/fixtures/cycle_a.bf"),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_include_once() {
+    let commands = parse_file("tests/fixtures/twice.bf", true).unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            BraincrapCommand::Import {
+                file: "inc.bf".to_string(),
+                path: canonical("tests/fixtures/inc.bf"),
+                alias: None,
+                tokens: spanned(vec![BraincrapToken::Plus(1)]),
+                code: spanned(vec![BraincrapCommand::Addition(1)]),
+            },
+            BraincrapCommand::Import {
+                file: "./inc.bf".to_string(),
+                path: canonical("tests/fixtures/inc.bf"),
+                alias: None,
+                tokens: vec![],
+                code: vec![],
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_include_every_time() {
+    let commands = parse_file("tests/fixtures/twice.bf", false).unwrap();
+
+    for command in &commands {
+        let BraincrapCommand::Import { code, .. } = &command.node else {
+            panic!("Expected an import, got {command:?}");
+        };
+        assert_eq!(code, &vec![BraincrapCommand::Addition(1)]);
+    }
+}
+
+#[test]
+fn test_parse_library_import_from_include_dirs() {
+    let path = PathBuf::from("tests/fixtures/uses_library.bf");
+    let tokens = Lexer::new(std::fs::read_to_string(&path).unwrap())
+        .tokenize()
+        .unwrap();
+
+    let mut parser = Parser::new(&tokens, PathBuf::from("tests/fixtures"));
+    parser.set_include_dirs(vec![
+        PathBuf::from("tests/missing"),
+        PathBuf::from("tests/fixtures/lib"),
+    ]);
+    let commands = parser.parse().unwrap();
+
+    let BraincrapCommand::Import { code, .. } = &commands[0].node else {
+        panic!("Expected an import, got {:?}", commands[0]);
+    };
+    assert!(matches!(
+        code[0].node,
+        BraincrapCommand::DefineMacro { ref name, .. } if name == "L"
+    ));
+}
+
+#[test]
+fn test_parse_relative_import_ignores_include_dirs() {
+    let path = PathBuf::from("tests/fixtures/uses_relative.bf");
+    let tokens = Lexer::new(std::fs::read_to_string(&path).unwrap())
+        .tokenize()
+        .unwrap();
+
+    let mut parser = Parser::new(&tokens, PathBuf::from("tests/fixtures"));
+    parser.set_include_dirs(vec![PathBuf::from("tests/fixtures/lib")]);
+    let result = parser.parse();
+
+    let Err(BraincrapError::ImportNotFound { path, .. }) = result else {
+        panic!("Expected a missing import, got {result:?}");
+    };
+    assert_eq!(path, PathBuf::from("tests/fixtures/./library.bf"));
+}
@@ -0,0 +1,104 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::{BraincrapError, BraincrapWarning};
+use braincrap_rs::interpreter::Interpreter;
+use braincrap_rs::parser::Parser;
+use braincrap_rs::span::{SourceMap, Span};
+use braincrap_rs::tokenizer::Lexer;
+use std::path::PathBuf;
+
+fn run(path: &str, source: &str) -> (SourceMap, BraincrapError) {
+    let mut sources = SourceMap::new();
+    let file = sources.add(PathBuf::from(path), source.to_string());
+    let tokens = Lexer::with_file(source.to_string(), file)
+        .tokenize()
+        .unwrap();
+    let mut parser = Parser::with_sources(&tokens, PathBuf::from("."), sources);
+    let commands = parser.parse().unwrap();
+
+    let mut output = Vec::new();
+    let error = Interpreter::new(&[][..], &mut output)
+        .run(commands)
+        .unwrap_err();
+    (parser.into_sources(), error)
+}
+
+#[test]
+fn test_span_to() {
+    let a = Span {
+        start: 4,
+        end: 5,
+        line: 1,
+        column: 5,
+        ..Span::default()
+    };
+    let b = Span {
+        start: 7,
+        end: 10,
+        line: 1,
+        column: 8,
+        ..Span::default()
+    };
+
+    assert_eq!(a.to(b), Span { end: 10, ..a });
+    assert_eq!(b.to(a), Span { end: 10, ..a });
+}
+
+#[test]
+fn test_render_unmatched_bracket() {
+    let (sources, error) = run("std.bf", "+\n\t>>]\n");
+
+    assert_eq!(
+        sources.render(&error),
+        "std.bf:2:4: Unmatched ']'\n\t>>]\n\t  ^"
+    );
+}
+
+#[test]
+fn test_render_error_inside_macro() {
+    // The error points into the macro body, not at the call
+    let (sources, error) = run("lib.bf", "#L <<\n>L");
+
+    assert!(matches!(
+        error,
+        BraincrapError::PointerOutOfBounds { pointer: -1, .. }
+    ));
+    assert_eq!(
+        sources.render(&error),
+        "lib.bf:1:4: Pointer moved outside of the tape: -1\n#L <<\n   ^"
+    );
+}
+
+#[test]
+fn test_render_without_span() {
+    let error = BraincrapError::ImportNotFound {
+        path: PathBuf::from("missing.bf"),
+        span: None,
+    };
+
+    assert_eq!(
+        SourceMap::new().render(&error),
+        "Failed to read file: missing.bf"
+    );
+}
+
+#[test]
+fn test_render_warning() {
+    let mut sources = SourceMap::new();
+    let file = sources.add(PathBuf::from("main.bf"), "#A +\n#A -\n".to_string());
+    let span = |line, column| Span {
+        file,
+        line,
+        column,
+        ..Span::default()
+    };
+    let warning = BraincrapWarning::ShadowedMacro {
+        name: "A".to_string(),
+        span: span(2, 1),
+        previous: span(1, 1),
+    };
+
+    assert_eq!(
+        sources.render_warning(&warning),
+        "main.bf:2:1: warning: Macro A shadows an existing definition\n#A -\n^"
+    );
+}
@@ -0,0 +1,125 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::interpreter::Interpreter;
+use braincrap_rs::parser::Parser;
+use braincrap_rs::stdlib;
+use braincrap_rs::tokenizer::Lexer;
+use std::path::PathBuf;
+
+/// Imports the standard library, runs `source` and returns the first cells, the pointer and
+/// the output.
+fn run(source: &str) -> (Vec<i64>, usize, Vec<u8>) {
+    let source = format!("$@std\n{source}");
+    let tokens = Lexer::new(source).tokenize().unwrap();
+    let mut parser = Parser::new(&tokens, PathBuf::from("."));
+    let commands = parser.parse().unwrap();
+
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(&[][..], &mut output);
+    interpreter.run(commands).unwrap();
+    let tape = interpreter.tape()[..8].to_vec();
+    let pointer = interpreter.pointer();
+    (tape, pointer, output)
+}
+
+/// Builds code that sets the first cells to `values` and moves back to cell 0.
+fn setup(values: &[usize]) -> String {
+    let mut code = String::new();
+    for value in values {
+        code.push_str(&"+".repeat(*value));
+        code.push('>');
+    }
+    code.push_str(&"<".repeat(values.len()));
+    code
+}
+
+#[test]
+fn test_stdlib_lookup() {
+    assert!(stdlib::get("std").is_some());
+    assert!(stdlib::get("missing").is_none());
+    assert_eq!(stdlib::names().collect::<Vec<_>>(), vec!["std"]);
+    assert_eq!(stdlib::VERSION, env!("CARGO_PKG_VERSION"));
+}
+
+#[test]
+fn test_stdlib_zero() {
+    assert_eq!(run(&format!("{}@zero", setup(&[42]))).0[0], 0);
+}
+
+#[test]
+fn test_stdlib_add() {
+    let (tape, pointer, _) = run(&format!("{}@add", setup(&[5, 7])));
+
+    assert_eq!(&tape[..2], &[12, 0]);
+    assert_eq!(pointer, 0);
+}
+
+#[test]
+fn test_stdlib_subtract() {
+    let (tape, pointer, _) = run(&format!("{}@sub", setup(&[9, 4])));
+
+    assert_eq!(&tape[..2], &[5, 0]);
+    assert_eq!(pointer, 0);
+}
+
+#[test]
+fn test_stdlib_multiply() {
+    let (tape, pointer, _) = run(&format!("{}@mul", setup(&[6, 7])));
+
+    assert_eq!(&tape[..4], &[0, 7, 42, 0]);
+    assert_eq!(pointer, 2);
+}
+
+#[test]
+fn test_stdlib_divmod() {
+    let (tape, pointer, _) = run(&format!("{}@divmod", setup(&[17, 5])));
+
+    assert_eq!(&tape[..6], &[0, 3, 2, 3, 0, 0]);
+    assert_eq!(pointer, 0);
+}
+
+#[test]
+fn test_stdlib_duplicate() {
+    let (tape, pointer, _) = run(&format!("{}@dup", setup(&[7])));
+
+    assert_eq!(&tape[..3], &[7, 7, 0]);
+    assert_eq!(pointer, 0);
+}
+
+#[test]
+fn test_stdlib_shift() {
+    let (tape, pointer, _) = run(&format!("{}@shift", setup(&[5])));
+
+    assert_eq!(&tape[..2], &[0, 5]);
+    assert_eq!(pointer, 0);
+}
+
+#[test]
+fn test_stdlib_swap() {
+    let (tape, pointer, _) = run(&format!("{}@swap", setup(&[3, 5])));
+
+    assert_eq!(&tape[..3], &[5, 3, 0]);
+    assert_eq!(pointer, 0);
+}
+
+#[test]
+fn test_stdlib_print_number() {
+    for (value, expected) in [(0, "0"), (7, "7"), (42, "42"), (105, "105"), (255, "255")] {
+        let (tape, pointer, output) = run(&format!("{}@print_num", setup(&[value])));
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        assert_eq!(tape, vec![value as i64, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(pointer, 0);
+    }
+}
+
+#[test]
+fn test_stdlib_unknown_library() {
+    let tokens = Lexer::new("$@missing".to_string()).tokenize().unwrap();
+    let mut parser = Parser::new(&tokens, PathBuf::from("."));
+
+    assert!(matches!(
+        parser.parse(),
+        Err(BraincrapError::ImportNotFound { .. })
+    ));
+}
@@ -0,0 +1,96 @@
+#![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::tape::{EofBehavior, Overflow, TapeConfig};
+
+#[test]
+fn test_default_config() {
+    let config = TapeConfig::default();
+
+    assert_eq!(config.cell_bits, 8);
+    assert!(!config.signed);
+    assert_eq!(config.overflow, Overflow::Wrap);
+    assert_eq!(config.tape_length, 30000);
+    assert_eq!(config.eof, EofBehavior::MinusOne);
+    assert_eq!(config.c_type(), "uint8_t");
+}
+
+#[test]
+fn test_cell_ranges() {
+    let signed_16 = TapeConfig {
+        cell_bits: 16,
+        signed: true,
+        ..TapeConfig::default()
+    };
+    let unsigned_32 = TapeConfig {
+        cell_bits: 32,
+        ..TapeConfig::default()
+    };
+
+    assert_eq!((signed_16.min(), signed_16.max()), (-32768, 32767));
+    assert_eq!((unsigned_32.min(), unsigned_32.max()), (0, 4_294_967_295));
+    assert_eq!(signed_16.c_type(), "int16_t");
+}
+
+#[test]
+fn test_add_wrap() {
+    let unsigned = TapeConfig::default();
+    let signed = TapeConfig {
+        signed: true,
+        ..TapeConfig::default()
+    };
+
+    assert_eq!(unsigned.add(255, 1), Some(0));
+    assert_eq!(unsigned.add(0, -1), Some(255));
+    assert_eq!(unsigned.add(0, 513), Some(1));
+    assert_eq!(signed.add(127, 1), Some(-128));
+    assert_eq!(signed.add(-128, -1), Some(127));
+}
+
+#[test]
+fn test_add_saturate_and_error() {
+    let saturate = TapeConfig {
+        overflow: Overflow::Saturate,
+        ..TapeConfig::default()
+    };
+    let error = TapeConfig {
+        overflow: Overflow::Error,
+        ..TapeConfig::default()
+    };
+
+    assert_eq!(saturate.add(250, 10), Some(255));
+    assert_eq!(saturate.add(5, -10), Some(0));
+    assert_eq!(error.add(250, 5), Some(255));
+    assert_eq!(error.add(250, 10), None);
+    assert_eq!(error.add(0, -1), None);
+}
+
+#[test]
+fn test_validate() {
+    let odd_width = TapeConfig {
+        cell_bits: 12,
+        ..TapeConfig::default()
+    };
+    let empty = TapeConfig {
+        tape_length: 0,
+        ..TapeConfig::default()
+    };
+
+    assert!(TapeConfig::default().validate().is_ok());
+    assert!(matches!(
+        odd_width.validate(),
+        Err(BraincrapError::InvalidTapeConfig { .. })
+    ));
+    assert!(matches!(
+        empty.validate(),
+        Err(BraincrapError::InvalidTapeConfig { .. })
+    ));
+}
+
+#[test]
+fn test_parse_behaviors() {
+    assert_eq!("saturate".parse(), Ok(Overflow::Saturate));
+    assert_eq!("0".parse(), Ok(EofBehavior::Zero));
+    assert_eq!("-1".parse(), Ok(EofBehavior::MinusOne));
+    assert_eq!("unchanged".parse(), Ok(EofBehavior::Unchanged));
+    assert!("sometimes".parse::<Overflow>().is_err());
+}
@@ -1,11 +1,17 @@
 #![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::span::{FileId, Span, Spanned};
 use braincrap_rs::tokenizer::{BraincrapToken, Lexer};
 
+fn spanned<T>(items: Vec<T>) -> Vec<Spanned<T>> {
+    items.into_iter().map(Spanned::from).collect()
+}
+
 #[test]
 fn test_tokenize_basic_symbols() {
     let input = "+-><.,[]";
     let mut lexer = Lexer::new(input.to_string());
-    let tokens = lexer.tokenize();
+    let tokens = lexer.tokenize().unwrap();
 
     assert_eq!(
         tokens,
@@ -26,13 +32,13 @@ fn test_tokenize_basic_symbols() {
 fn test_tokenize_macro_and_string() {
     let input = "#a ....\n$filename.txt";
     let mut lexer = Lexer::new(input.to_string());
-    let tokens = lexer.tokenize();
+    let tokens = lexer.tokenize().unwrap();
 
     assert_eq!(
         tokens,
         vec![
             BraincrapToken::Hash,
-            BraincrapToken::Char('a'),
+            BraincrapToken::Name("a".to_string()),
             BraincrapToken::String("....".to_string()),
             BraincrapToken::Dollar,
             BraincrapToken::String("filename.txt".to_string()),
@@ -44,14 +50,170 @@ fn test_tokenize_macro_and_string() {
 fn test_tokenize_illegal_macro() {
     let input = "#> illegal_macro";
     let mut lexer = Lexer::new(input.to_string());
-    let tokens = lexer.tokenize();
+    let result = lexer.tokenize();
+
+    assert!(matches!(
+        result,
+        Err(BraincrapError::IllegalMacroName {
+            name: '>',
+            span: Span {
+                start: 1,
+                line: 1,
+                column: 2,
+                ..
+            }
+        })
+    ));
+}
+
+#[test]
+fn test_tokenize_multi_character_macros() {
+    let input = "#print_num .\n#* +\n@print_num*a";
+    let mut lexer = Lexer::new(input.to_string());
+    let tokens = lexer.tokenize().unwrap();
 
     assert_eq!(
         tokens,
         vec![
             BraincrapToken::Hash,
-            BraincrapToken::Char('e'), // Default char for illegal macro names
-            BraincrapToken::String("illegal_macro".to_string())
+            BraincrapToken::Name("print_num".to_string()),
+            BraincrapToken::String(".".to_string()),
+            BraincrapToken::Hash,
+            BraincrapToken::Name("*".to_string()),
+            BraincrapToken::String("+".to_string()),
+            BraincrapToken::Name("print_num".to_string()),
+            BraincrapToken::Char('*'),
+            BraincrapToken::Char('a'),
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_illegal_macro_call() {
+    let mut lexer = Lexer::new("+@+".to_string());
+
+    assert!(matches!(
+        lexer.tokenize(),
+        Err(BraincrapError::IllegalMacroName { name: '+', .. })
+    ));
+}
+
+#[test]
+fn test_tokenize_macro_arguments() {
+    let input = "#copy(src, dst, tmp=3) +\n@copy(0, {+,-}, tmp=1)@add()";
+    let mut lexer = Lexer::new(input.to_string());
+    let tokens = lexer.tokenize().unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            BraincrapToken::Hash,
+            BraincrapToken::Name("copy".to_string()),
+            BraincrapToken::Arguments(spanned(vec![
+                "src".to_string(),
+                "dst".to_string(),
+                "tmp=3".to_string()
+            ])),
+            BraincrapToken::String("+".to_string()),
+            BraincrapToken::Name("copy".to_string()),
+            BraincrapToken::Arguments(spanned(vec![
+                "0".to_string(),
+                "+,-".to_string(),
+                "tmp=1".to_string()
+            ])),
+            BraincrapToken::Name("add".to_string()),
+            BraincrapToken::Arguments(vec![]),
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_argument_spans() {
+    let mut lexer = Lexer::new("@copy(0,  {+,-},\n tmp=1)".to_string());
+    let tokens = lexer.tokenize().unwrap();
+
+    let BraincrapToken::Arguments(arguments) = &tokens[1].node else {
+        panic!("Expected arguments, got {tokens:?}");
+    };
+    let positions: Vec<(usize, usize, usize, usize)> = arguments
+        .iter()
+        .map(|a| (a.span.start, a.span.end, a.span.line, a.span.column))
+        .collect();
+    assert_eq!(
+        positions,
+        vec![(6, 7, 1, 7), (11, 14, 1, 12), (18, 23, 2, 2)]
+    );
+}
+
+#[test]
+fn test_tokenize_unclosed_arguments() {
+    let mut lexer = Lexer::new("@move(1".to_string());
+
+    assert!(matches!(
+        lexer.tokenize(),
+        Err(BraincrapError::UnbalancedBrackets { bracket: '(', .. })
+    ));
+}
+
+#[test]
+fn test_tokenize_macro_block() {
+    let input = "#twice(x) {\n    ; Braces in comments } are ignored\n\n    %x%x\n}+";
+    let mut lexer = Lexer::new(input.to_string());
+    let tokens = lexer.tokenize().unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            BraincrapToken::Hash,
+            BraincrapToken::Name("twice".to_string()),
+            BraincrapToken::Arguments(spanned(vec!["x".to_string()])),
+            BraincrapToken::String(
+                "\n    ; Braces in comments } are ignored\n\n    %x%x\n".to_string()
+            ),
+            BraincrapToken::Plus(1),
+        ]
+    );
+    assert_eq!((tokens[3].span.line, tokens[3].span.column), (1, 12));
+    assert_eq!((tokens[4].span.line, tokens[4].span.column), (5, 2));
+}
+
+#[test]
+fn test_tokenize_unclosed_macro_block() {
+    let mut lexer = Lexer::new("+\n#a {\n+".to_string());
+
+    assert!(matches!(
+        lexer.tokenize(),
+        Err(BraincrapError::UnbalancedBrackets {
+            bracket: '{',
+            span: Span {
+                line: 2,
+                column: 4,
+                ..
+            }
+        })
+    ));
+}
+
+#[test]
+fn test_tokenize_namespaces() {
+    let input = "$std.bf as s\n$other.bf\ns::add@s::inner::zero(1)a::";
+    let mut lexer = Lexer::new(input.to_string());
+    let tokens = lexer.tokenize().unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            BraincrapToken::Dollar,
+            BraincrapToken::String("std.bf".to_string()),
+            BraincrapToken::Alias("s".to_string()),
+            BraincrapToken::Dollar,
+            BraincrapToken::String("other.bf".to_string()),
+            BraincrapToken::Name("s::add".to_string()),
+            BraincrapToken::Name("s::inner::zero".to_string()),
+            BraincrapToken::Arguments(spanned(vec!["1".to_string()])),
+            BraincrapToken::Char('a'),
+            BraincrapToken::Char(':'),
+            BraincrapToken::Char(':'),
         ]
     );
 }
@@ -60,7 +222,7 @@ fn test_tokenize_illegal_macro() {
 fn test_tokenize_empty_input() {
     let input = "";
     let mut lexer = Lexer::new(input.to_string());
-    let tokens = lexer.tokenize();
+    let tokens = lexer.tokenize().unwrap();
 
     assert_eq!(tokens, Vec::<BraincrapToken>::new());
 }
@@ -69,10 +231,34 @@ fn test_tokenize_empty_input() {
 fn test_tokenize_whitespace_and_comments() {
     let input = "   ; This is a comment\n + -";
     let mut lexer = Lexer::new(input.to_string());
-    let tokens = lexer.tokenize();
+    let tokens = lexer.tokenize().unwrap();
 
     assert_eq!(
         tokens,
         vec![BraincrapToken::Plus(1), BraincrapToken::Minus(1),]
     );
 }
+
+#[test]
+fn test_tokenize_spans() {
+    let input = "++\n #a >.\n\t]";
+    let mut lexer = Lexer::with_file(input.to_string(), FileId(3));
+    let tokens = lexer.tokenize().unwrap();
+
+    let spans: Vec<(usize, usize, usize, usize)> = tokens
+        .iter()
+        .map(|t| (t.span.start, t.span.end, t.span.line, t.span.column))
+        .collect();
+
+    assert_eq!(
+        spans,
+        vec![
+            (0, 2, 1, 1),   // ++
+            (4, 5, 2, 2),   // #
+            (5, 6, 2, 3),   // a
+            (7, 9, 2, 5),   // >.
+            (11, 12, 3, 2), // ]
+        ]
+    );
+    assert!(tokens.iter().all(|t| t.span.file == FileId(3)));
+}
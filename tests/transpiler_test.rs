@@ -1,10 +1,19 @@
 #![allow(unexpected_cfgs)]
+use braincrap_rs::error::BraincrapError;
+use braincrap_rs::optimizer::PassManager;
 use braincrap_rs::parser::BraincrapCommand;
+use braincrap_rs::span::Spanned;
+use braincrap_rs::tape::{EofBehavior, Overflow, TapeConfig};
 use braincrap_rs::transpiler::{Transpiler, TranspilerArguments};
+use std::path::PathBuf;
+
+fn spanned<T>(items: Vec<T>) -> Vec<Spanned<T>> {
+    items.into_iter().map(Spanned::from).collect()
+}
 
 #[test]
 fn test_transpile_basic_commands() {
-    let commands = vec![
+    let commands = spanned(vec![
         BraincrapCommand::Addition(1),
         BraincrapCommand::Substraction(1),
         BraincrapCommand::MoveLeft(1),
@@ -13,11 +22,16 @@ fn test_transpile_basic_commands() {
         BraincrapCommand::CloseLoop,
         BraincrapCommand::Output(1),
         BraincrapCommand::Input(1),
-    ];
+    ]);
 
     let mut transpiler = Transpiler::new();
-    let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
-    let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
+    transpiler.set_passes(PassManager::with_level(0));
+    let bf_result = transpiler
+        .transpile(commands.clone(), &TranspilerArguments::Brainfuck)
+        .unwrap();
+    let c_result = transpiler
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
 
     assert_eq!(c_result, "(*ptr += 1);(*ptr -= 1);(ptr -= 1);(ptr += 1);while(*ptr != 0){}putchar(*ptr);(*ptr = getchar());");
     assert_eq!(bf_result, "+-<>[].,");
@@ -25,59 +39,109 @@ fn test_transpile_basic_commands() {
 
 #[test]
 fn test_transpile_macro_definition_and_run() {
-    let commands = vec![
+    let commands = spanned(vec![
         BraincrapCommand::DefineMacro {
-            name: 'a',
+            name: "a".to_string(),
+            parameters: vec![],
             tokens: vec![],
-            code: vec![
+            code: spanned(vec![
                 BraincrapCommand::Addition(1),
                 BraincrapCommand::MoveRight(1),
-            ],
+            ]),
         },
-        BraincrapCommand::RunMacro { name: 'a' },
-        BraincrapCommand::RunMacro { name: 'a' },
-    ];
+        BraincrapCommand::RunMacro {
+            name: "a".to_string(),
+        },
+        BraincrapCommand::RunMacro {
+            name: "a".to_string(),
+        },
+    ]);
 
     let mut transpiler = Transpiler::new();
-    let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
-    let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
+    let bf_result = transpiler
+        .transpile(commands.clone(), &TranspilerArguments::Brainfuck)
+        .unwrap();
+    let c_result = transpiler
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
 
     assert_eq!(bf_result, "+>+>");
-    assert_eq!(c_result, "(*ptr += 1);(ptr += 1);(*ptr += 1);(ptr += 1);");
+    assert_eq!(c_result, "(*ptr += 1);(ptr[1] += 1);(ptr += 2);");
 }
 
 #[test]
 fn test_transpile_macro_without_definition() {
-    let commands = vec![BraincrapCommand::RunMacro { name: 'b' }];
+    let commands = spanned(vec![BraincrapCommand::RunMacro {
+        name: "b".to_string(),
+    }]);
 
     let mut transpiler = Transpiler::new();
     let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
     let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
 
-    assert_eq!(bf_result, "");
-    assert_eq!(c_result, "");
+    assert!(matches!(
+        bf_result,
+        Err(BraincrapError::UndefinedMacro { name, .. }) if name == "b"
+    ));
+    assert!(matches!(
+        c_result,
+        Err(BraincrapError::UndefinedMacro { name, .. }) if name == "b"
+    ));
+}
+
+#[test]
+fn test_transpile_unbalanced_loops() {
+    let commands = spanned(vec![
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::CloseLoop,
+    ]);
+
+    let mut transpiler = Transpiler::new();
+    let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
+    let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
+
+    assert!(matches!(
+        bf_result,
+        Err(BraincrapError::UnbalancedBrackets { .. })
+    ));
+    assert!(matches!(
+        c_result,
+        Err(BraincrapError::UnbalancedBrackets { .. })
+    ));
 }
 
 #[test]
 fn test_transpile_macro_with_redefinition() {
-    let commands = vec![
+    let commands = spanned(vec![
         BraincrapCommand::DefineMacro {
-            name: 'a',
+            name: "a".to_string(),
+            parameters: vec![],
             tokens: vec![],
-            code: vec![BraincrapCommand::Addition(1)],
+            code: spanned(vec![BraincrapCommand::Addition(1)]),
+        },
+        BraincrapCommand::RunMacro {
+            name: "a".to_string(),
         },
-        BraincrapCommand::RunMacro { name: 'a' },
         BraincrapCommand::DefineMacro {
-            name: 'a',
+            name: "a".to_string(),
+            parameters: vec![],
             tokens: vec![],
-            code: vec![BraincrapCommand::Substraction(1)],
+            code: spanned(vec![BraincrapCommand::Substraction(1)]),
+        },
+        BraincrapCommand::RunMacro {
+            name: "a".to_string(),
         },
-        BraincrapCommand::RunMacro { name: 'a' },
-    ];
+    ]);
 
     let mut transpiler = Transpiler::new();
-    let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
-    let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
+    transpiler.set_passes(PassManager::with_level(0));
+    let bf_result = transpiler
+        .transpile(commands.clone(), &TranspilerArguments::Brainfuck)
+        .unwrap();
+    let c_result = transpiler
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
 
     assert_eq!(bf_result, "+-");
     assert_eq!(c_result, "(*ptr += 1);(*ptr -= 1);");
@@ -85,15 +149,24 @@ fn test_transpile_macro_with_redefinition() {
 
 #[test]
 fn test_transpile_import() {
-    let commands = vec![BraincrapCommand::Import {
+    let commands = spanned(vec![BraincrapCommand::Import {
         file: "other.bcf".to_string(),
+        path: PathBuf::from("other.bcf"),
+        alias: None,
         tokens: vec![],
-        code: vec![BraincrapCommand::Addition(1), BraincrapCommand::MoveLeft(1)],
-    }];
+        code: spanned(vec![
+            BraincrapCommand::Addition(1),
+            BraincrapCommand::MoveLeft(1),
+        ]),
+    }]);
 
     let mut transpiler = Transpiler::new();
-    let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
-    let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
+    let bf_result = transpiler
+        .transpile(commands.clone(), &TranspilerArguments::Brainfuck)
+        .unwrap();
+    let c_result = transpiler
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
 
     assert_eq!(bf_result, "+<");
     assert_eq!(c_result, "(*ptr += 1);(ptr -= 1);");
@@ -101,12 +174,293 @@ fn test_transpile_import() {
 
 #[test]
 fn test_transpile_empty_input() {
-    let commands: Vec<BraincrapCommand> = vec![];
+    let commands: Vec<Spanned<BraincrapCommand>> = vec![];
 
     let mut transpiler = Transpiler::new();
-    let bf_result = transpiler.transpile(commands.clone(), &TranspilerArguments::Brainfuck);
-    let c_result = transpiler.transpile(commands, &TranspilerArguments::C);
+    let bf_result = transpiler
+        .transpile(commands.clone(), &TranspilerArguments::Brainfuck)
+        .unwrap();
+    let c_result = transpiler
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
 
     assert_eq!(bf_result, "");
     assert_eq!(c_result, "");
 }
+
+#[test]
+fn test_transpile_c_tape_config() {
+    let commands = spanned(vec![
+        BraincrapCommand::Addition(2),
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::Input(1),
+    ]);
+    let config = TapeConfig {
+        cell_bits: 16,
+        overflow: Overflow::Saturate,
+        tape_length: 100,
+        eof: EofBehavior::Zero,
+        ..TapeConfig::default()
+    };
+
+    let mut transpiler = Transpiler::with_config(config);
+    let c_result = transpiler
+        .transpile(commands, &TranspilerArguments::C)
+        .unwrap();
+    let program = transpiler.c_program(&c_result);
+
+    assert_eq!(
+        c_result,
+        "cell_add(ptr, 2);cell_add(ptr, -1);{int c = getchar(); *ptr = c == EOF ? 0 : c;}"
+    );
+    assert!(program.contains("static uint16_t tape[100] = {0};"));
+    assert!(program.contains("if (v > 65535LL) v = 65535LL;"));
+}
+
+#[test]
+fn test_transpile_rust() {
+    let commands = spanned(vec![
+        BraincrapCommand::Input(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(2),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(1),
+    ]);
+
+    let mut transpiler = Transpiler::new();
+    let rust_result = transpiler
+        .transpile(commands, &TranspilerArguments::Rust)
+        .unwrap();
+    let program = transpiler.rust_program(&rust_result);
+
+    assert_eq!(
+        rust_result,
+        "tape[ptr] = read(&mut out).map_or(255, |b| b as u8);\
+         while tape[ptr] != 0 {tape[ptr] = tape[ptr].wrapping_sub(1);ptr -= 2;}\
+         out.write_all(&[tape[ptr] as u8]).unwrap();"
+    );
+    assert!(program.contains("let mut tape = vec![0u8; 30000];"));
+    assert!(!program.contains("fn cell_add"));
+}
+
+#[test]
+fn test_transpile_rust_tape_config() {
+    let commands = spanned(vec![
+        BraincrapCommand::Addition(2),
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::Input(1),
+    ]);
+    let config = TapeConfig {
+        cell_bits: 16,
+        signed: true,
+        overflow: Overflow::Error,
+        tape_length: 100,
+        eof: EofBehavior::Unchanged,
+    };
+
+    let mut transpiler = Transpiler::with_config(config);
+    let rust_result = transpiler
+        .transpile(commands, &TranspilerArguments::Rust)
+        .unwrap();
+    let program = transpiler.rust_program(&rust_result);
+
+    assert_eq!(
+        rust_result,
+        "cell_add(&mut tape[ptr], 2);cell_add(&mut tape[ptr], -1);\
+         if let Some(b) = read(&mut out) {tape[ptr] = b as i16;}"
+    );
+    assert!(program.contains("let mut tape = vec![0i16; 100];"));
+    assert!(program.contains("if !(-32768..=32767).contains(&v)"));
+}
+
+#[test]
+fn test_transpile_wat() {
+    let commands = spanned(vec![
+        BraincrapCommand::Input(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(2),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(1),
+    ]);
+    let config = TapeConfig {
+        cell_bits: 16,
+        tape_length: 40000,
+        ..TapeConfig::default()
+    };
+
+    let mut transpiler = Transpiler::with_config(config);
+    let wat_result = transpiler
+        .transpile(commands, &TranspilerArguments::Wat)
+        .unwrap();
+    let program = transpiler.wat_program(&wat_result);
+
+    assert_eq!(
+        wat_result,
+        "\
+(i32.store16 (local.get $ptr) (call $read))
+(block
+  (loop
+    (br_if 1 (i32.eqz (i32.load16_u (local.get $ptr))))
+    (i32.store16 (local.get $ptr) (i32.sub (i32.load16_u (local.get $ptr)) (i32.const 1)))
+    (local.set $ptr (i32.sub (local.get $ptr) (i32.const 4)))
+    (br 0)))
+(call $write (i32.load16_u (local.get $ptr)))
+"
+    );
+    assert!(program.contains("(memory (export \"memory\") 2)"));
+    assert!(!program.contains("$cell_add"));
+}
+
+#[test]
+fn test_transpile_asm() {
+    let commands = spanned(vec![
+        BraincrapCommand::Input(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(2),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(1),
+    ]);
+    let config = TapeConfig {
+        cell_bits: 32,
+        eof: EofBehavior::Zero,
+        ..TapeConfig::default()
+    };
+
+    let mut transpiler = Transpiler::with_config(config);
+    let asm_result = transpiler
+        .transpile(commands, &TranspilerArguments::Asm)
+        .unwrap();
+    let program = transpiler.asm_program(&asm_result);
+
+    assert_eq!(
+        asm_result,
+        "\tcall get\n\ttest eax, eax\n\tjns 1f\n\txor eax, eax\n1:\n\tmov dword ptr [rbx], eax\n\
+         .Lloop0:\n\tcmp dword ptr [rbx], 0\n\tje .Lend0\n\tsub dword ptr [rbx], 1\n\tsub rbx, 8\n\
+         \tjmp .Lloop0\n.Lend0:\n\tlea rdi, [rbx]\n\tcall put\n"
+    );
+    assert!(program.contains("tape:\n\t.zero 120000\n"));
+    assert!(!program.contains("cell_add"));
+}
+
+#[test]
+fn test_transpile_llvm() {
+    let commands = spanned(vec![
+        BraincrapCommand::Input(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(2),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(1),
+    ]);
+
+    let mut transpiler = Transpiler::new();
+    let llvm_result = transpiler
+        .transpile(commands, &TranspilerArguments::Llvm)
+        .unwrap();
+    let program = transpiler.llvm_program(&llvm_result);
+
+    assert_eq!(
+        llvm_result,
+        "  %t0 = load ptr, ptr %ptr
+  %t1 = call i32 @getchar()
+  %t2 = trunc i32 %t1 to i8
+  store i8 %t2, ptr %t0
+  br label %loop3
+loop3:
+  %t4 = load ptr, ptr %ptr
+  %t5 = load i8, ptr %t4
+  %t6 = icmp ne i8 %t5, 0
+  br i1 %t6, label %body3, label %end3
+body3:
+  %t7 = load ptr, ptr %ptr
+  %t8 = load i8, ptr %t7
+  %t9 = add i8 %t8, 255
+  store i8 %t9, ptr %t7
+  %t10 = load ptr, ptr %ptr
+  %t11 = getelementptr i8, ptr %t10, i64 -2
+  store ptr %t11, ptr %ptr
+  br label %loop3
+end3:
+  %t12 = load ptr, ptr %ptr
+  %t13 = load i8, ptr %t12
+  %t14 = zext i8 %t13 to i32
+  %t15 = call i32 @putchar(i32 %t14)
+"
+    );
+    assert!(program.contains("@tape = internal global [30000 x i8] zeroinitializer"));
+    assert!(!program.contains("@cell_add"));
+}
+
+#[test]
+fn test_transpile_js() {
+    let commands = spanned(vec![
+        BraincrapCommand::Input(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(2),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(1),
+    ]);
+    let config = TapeConfig {
+        signed: true,
+        overflow: Overflow::Saturate,
+        ..TapeConfig::default()
+    };
+
+    let mut transpiler = Transpiler::with_config(config);
+    let js_result = transpiler
+        .transpile(commands, &TranspilerArguments::Js)
+        .unwrap();
+    let program = transpiler.js_program(&js_result);
+
+    assert_eq!(
+        js_result,
+        "tape[ptr] = pos < input.length ? input[pos++] : -1;\
+         while (tape[ptr]) {cellAdd(ptr, -1);ptr -= 2;}output.push(tape[ptr]);"
+    );
+    assert!(program.contains("const tape = new Int8Array(30000);"));
+    assert!(program.contains("tape[index] = v < -128 ? -128 : v > 127 ? 127 : v;"));
+    assert!(program.contains("function run(inputString)"));
+    assert!(program.contains("error.output = Uint8Array.from(output);"));
+    assert!(!program.contains("process.stdout"));
+    assert!(transpiler
+        .node_program(&js_result)
+        .contains("if (error.output) process.stdout.write(error.output);"));
+}
+
+#[test]
+fn test_transpile_python() {
+    let commands = spanned(vec![
+        BraincrapCommand::Input(1),
+        BraincrapCommand::OpenLoop,
+        BraincrapCommand::Substraction(1),
+        BraincrapCommand::MoveLeft(2),
+        BraincrapCommand::CloseLoop,
+        BraincrapCommand::Output(2),
+    ]);
+
+    let mut transpiler = Transpiler::new();
+    let python_result = transpiler
+        .transpile(commands, &TranspilerArguments::Python)
+        .unwrap();
+    let program = transpiler.python_program(&python_result);
+
+    assert_eq!(
+        python_result,
+        "\
+byte = read(out)
+tape[ptr] = 255 if byte is None else byte
+while tape[ptr]:
+    tape[ptr] = (tape[ptr] - 1) & 255
+    ptr -= 2
+    if ptr < 0: raise IndexError(\"Pointer left the tape\")
+out.write(bytes((tape[ptr],)) * 2)
+"
+    );
+    assert!(program.contains("    tape = bytearray(30000)\n"));
+    assert!(program.contains("def scan_left(tape, ptr):"));
+    assert!(!program.contains("def check"));
+}